@@ -0,0 +1,1281 @@
+use rocket::http::Status;
+use rocket::response::status::{Created, Custom, NoContent};
+use rocket::serde::json::Json;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::config::CalDavConfig;
+use crate::health::Health;
+use crate::manager::{
+    self, Assignment, Job, JobCommand, JobRun, RemovedPolicy, RetryPolicy, ScriptAssignment,
+    ScriptOutput, SyncPreview,
+};
+use crate::protocol::{Message, Progress};
+use crate::schedule::Schedule;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+pub type ApiError = Custom<Json<ErrorBody>>;
+pub type ApiResult<T> = Result<T, ApiError>;
+
+fn error(status: Status, message: impl Into<String>) -> ApiError {
+    Custom(
+        status,
+        Json(ErrorBody {
+            error: message.into(),
+        }),
+    )
+}
+
+fn not_found(what: &str) -> ApiError {
+    error(Status::NotFound, format!("{} not found", what))
+}
+
+fn storage_error(e: std::io::Error) -> ApiError {
+    error(Status::InternalServerError, e.to_string())
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct NewAssignment {
+    pub name: String,
+    pub course: String,
+    pub due: u64,
+    pub job_name: Option<String>,
+}
+
+/// Only the fields that are present get changed.
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema)]
+pub struct AssignmentPatch {
+    pub name: Option<String>,
+    pub course: Option<String>,
+    pub due: Option<u64>,
+    pub done: Option<bool>,
+    pub dismissed: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct NewJob {
+    pub name: String,
+    pub path: String,
+    pub sync_to_google: bool,
+    pub sync_interval: u64,
+    /// Defaults to `node index.js` in `path`.
+    pub command: Option<JobCommand>,
+    /// Seconds before a run is killed, 0 for no limit. Defaults to 5 minutes.
+    pub timeout: Option<u64>,
+    pub retry: Option<RetryPolicy>,
+    /// Cron expression, time of day window and weekdays. Runs every `sync_interval` by default.
+    pub schedule: Option<Schedule>,
+    /// What to do with assignments the job stops returning. Defaults to flagging them.
+    pub removed_policy: Option<RemovedPolicy>,
+    /// A CalDAV calendar for this job's assignments. Defaults to the configured calendar.
+    pub caldav: Option<CalDavConfig>,
+}
+
+/// Only the fields that are present get changed.
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema)]
+pub struct JobPatch {
+    pub path: Option<String>,
+    pub sync_to_google: Option<bool>,
+    pub sync_interval: Option<u64>,
+    pub command: Option<JobCommand>,
+    pub timeout: Option<u64>,
+    pub retry: Option<RetryPolicy>,
+    pub schedule: Option<Schedule>,
+    pub removed_policy: Option<RemovedPolicy>,
+    /// An empty `url` goes back to the configured calendar. Leaving out `password` or
+    /// `password_file` keeps the current one.
+    pub caldav: Option<CalDavConfig>,
+    /// Setting this to false resumes a job that was paused after failing too often.
+    pub paused: Option<bool>,
+}
+
+impl JobPatch {
+    fn apply(self, job: &mut Job) -> Result<(), String> {
+        if let Some(schedule) = self.schedule {
+            let schedule = schedule.normalize();
+            schedule.validate()?;
+            if schedule != job.schedule {
+                job.schedule = schedule;
+                job.reschedule();
+            }
+        }
+        if let Some(path) = self.path {
+            job.path = Job::fix_home(path);
+        }
+        if let Some(command) = self.command {
+            job.command = command;
+        }
+        if let Some(timeout) = self.timeout {
+            job.timeout = timeout;
+        }
+        if let Some(retry) = self.retry {
+            job.retry = retry;
+        }
+        if let Some(removed_policy) = self.removed_policy {
+            job.removed_policy = removed_policy;
+        }
+        if let Some(mut caldav) = self.caldav {
+            // the password never comes back from the API, so a job sent back as read keeps it
+            if let Some(current) = &job.caldav {
+                if caldav.password.is_none() {
+                    caldav.password = current.password.clone();
+                }
+                if caldav.password_file.is_none() {
+                    caldav.password_file = current.password_file.clone();
+                }
+            }
+            job.caldav = Some(caldav).filter(|caldav| !caldav.url.is_empty());
+        }
+        if let Some(sync_to_google) = self.sync_to_google {
+            job.sync_to_google = sync_to_google;
+        }
+        if let Some(sync_interval) = self.sync_interval.filter(|i| *i != job.sync_interval) {
+            job.sync_interval = sync_interval;
+            job.reschedule();
+        }
+        match self.paused {
+            Some(false) if job.paused => job.resume(),
+            Some(paused) => job.paused = paused,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    responses((status = 200, description = "Assignments matching every filter given", body = Vec<Assignment>))
+)]
+#[get("/assignments?<course>&<job>&<done>&<archived>&<due_after>&<due_before>")]
+fn list_assignments(
+    course: Option<String>,
+    job: Option<String>,
+    done: Option<bool>,
+    archived: Option<bool>,
+    due_after: Option<u64>,
+    due_before: Option<u64>,
+) -> Json<Vec<Assignment>> {
+    let manager = manager::Manager::read_no_save();
+    let assignments = manager
+        .assignments
+        .into_iter()
+        .filter(|a| course.as_ref().is_none_or(|c| a.course == *c))
+        .filter(|a| job.as_ref().is_none_or(|j| a.job_name.as_ref() == Some(j)))
+        .filter(|a| done.is_none_or(|d| a.done == d))
+        .filter(|a| archived.is_none_or(|d| a.archived_at.is_some() == d))
+        .filter(|a| due_after.is_none_or(|t| a.due >= t))
+        .filter(|a| due_before.is_none_or(|t| a.due < t))
+        .collect();
+    Json(assignments)
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    responses(
+        (status = 200, body = Assignment),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[get("/assignments/<id>")]
+fn get_assignment(id: String) -> ApiResult<Json<Assignment>> {
+    let manager = manager::Manager::read_no_save();
+    match manager.get_assignment(&id) {
+        Some(assignment) => Ok(Json(assignment.clone())),
+        None => Err(not_found("Assignment")),
+    }
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    request_body = NewAssignment,
+    responses(
+        (status = 201, body = Assignment),
+        (status = 422, body = ErrorBody)
+    )
+)]
+#[post("/assignments", data = "<new_assignment>")]
+fn create_assignment(new_assignment: Json<NewAssignment>) -> ApiResult<Created<Json<Assignment>>> {
+    let new_assignment = new_assignment.into_inner();
+    if new_assignment.name.is_empty() {
+        return Err(error(Status::UnprocessableEntity, "name can't be empty"));
+    }
+    let mut manager = manager::Manager::from_save_file();
+    let assignment = Assignment::new(
+        new_assignment.course,
+        new_assignment.due,
+        new_assignment.name,
+        new_assignment.job_name.unwrap_or("manual".to_string()),
+    );
+    manager.add_assignment(assignment.clone());
+    // add_assignment keeps the id of an assignment that already matches
+    let assignment = manager
+        .data
+        .assignments
+        .iter()
+        .find(|a| **a == assignment)
+        .unwrap()
+        .clone();
+    manager
+        .save_assignment(&assignment)
+        .map_err(storage_error)?;
+    Ok(Created::new(format!("/api/v1/assignments/{}", assignment.id)).body(Json(assignment)))
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    request_body = AssignmentPatch,
+    responses(
+        (status = 200, body = Assignment),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[patch("/assignments/<id>", data = "<patch>")]
+fn patch_assignment(id: String, patch: Json<AssignmentPatch>) -> ApiResult<Json<Assignment>> {
+    let mut manager = manager::Manager::from_save_file();
+    let assignment = match manager.data.assignments.iter_mut().find(|a| a.id == id) {
+        Some(v) => v,
+        None => return Err(not_found("Assignment")),
+    };
+    let patch = patch.into_inner();
+    if patch.name.is_some() || patch.course.is_some() || patch.due.is_some() {
+        // pushes the change to the calendar event
+        assignment.synced = None;
+    }
+    if let Some(name) = patch.name {
+        assignment.link_name = Assignment::get_link_name(name.clone());
+        assignment.name = name;
+    }
+    if let Some(course) = patch.course {
+        assignment.course = course;
+    }
+    if let Some(due) = patch.due {
+        assignment.due = due;
+    }
+    if let Some(done) = patch.done.filter(|done| *done != assignment.done) {
+        if done {
+            assignment.mark_done();
+        } else {
+            assignment.mark_undone();
+        }
+    }
+    if let Some(dismissed) = patch.dismissed {
+        assignment.dismissed = dismissed;
+    }
+    let assignment = assignment.clone();
+    manager
+        .save_assignment(&assignment)
+        .map_err(storage_error)?;
+    // to push it to the calendar
+    crate::scheduler::wake();
+    Ok(Json(assignment))
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    responses(
+        (status = 204),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[delete("/assignments/<id>")]
+fn delete_assignment(id: String) -> ApiResult<NoContent> {
+    let mut manager = manager::Manager::from_save_file();
+    let assignment = match manager.get_assignment(&id) {
+        Some(v) => v.clone(),
+        None => return Err(not_found("Assignment")),
+    };
+    manager
+        .delete_assignment(&assignment)
+        .map_err(storage_error)?;
+    // to take it off the calendar
+    crate::scheduler::wake();
+    Ok(NoContent)
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    responses(
+        (status = 200, body = Assignment),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[post("/assignments/<id>/done")]
+fn mark_assignment_done(id: String) -> ApiResult<Json<Assignment>> {
+    let mut manager = manager::Manager::from_save_file();
+    if manager.get_assignment(&id).is_none() {
+        return Err(not_found("Assignment"));
+    }
+    manager.mark_done(&id);
+    let assignment = manager.get_assignment(&id).unwrap().clone();
+    manager
+        .save_assignment(&assignment)
+        .map_err(storage_error)?;
+    // to push it to the calendar
+    crate::scheduler::wake();
+    Ok(Json(assignment))
+}
+
+#[utoipa::path(
+    tag = "assignments",
+    responses(
+        (status = 200, body = Assignment),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[delete("/assignments/<id>/done")]
+fn mark_assignment_undone(id: String) -> ApiResult<Json<Assignment>> {
+    let mut manager = manager::Manager::from_save_file();
+    if manager.get_assignment(&id).is_none() {
+        return Err(not_found("Assignment"));
+    }
+    manager.mark_undone(&id);
+    let assignment = manager.get_assignment(&id).unwrap().clone();
+    manager
+        .save_assignment(&assignment)
+        .map_err(storage_error)?;
+    // to push it to the calendar
+    crate::scheduler::wake();
+    Ok(Json(assignment))
+}
+
+#[utoipa::path(tag = "jobs", responses((status = 200, body = Vec<Job>)))]
+#[get("/jobs")]
+fn list_jobs() -> Json<Vec<Job>> {
+    Json(manager::Manager::read_no_save().redacted().jobs)
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 200, body = Job),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[get("/jobs/<id>")]
+fn get_job(id: String) -> ApiResult<Json<Job>> {
+    let manager = manager::Manager::read_no_save();
+    match manager.get_job_by_id(&id) {
+        Some(job) => Ok(Json(job.clone().redacted())),
+        None => Err(not_found("Job")),
+    }
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 200, description = "Newest first", body = Vec<JobRun>),
+        (status = 404, body = ErrorBody)
+    )
+)]
+/// Recent runs of a job, with their exit status and the end of their output.
+#[get("/jobs/<id>/runs")]
+fn list_job_runs(id: String) -> ApiResult<Json<Vec<JobRun>>> {
+    let manager = manager::Manager::read_no_save();
+    if manager.get_job_by_id(&id).is_none() {
+        return Err(not_found("Job"));
+    }
+    Ok(Json(manager.get_runs(&id)))
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    request_body = NewJob,
+    responses(
+        (status = 201, body = Job),
+        (status = 409, body = ErrorBody),
+        (status = 422, body = ErrorBody)
+    )
+)]
+#[post("/jobs", data = "<new_job>")]
+fn create_job(new_job: Json<NewJob>) -> ApiResult<Created<Json<Job>>> {
+    let new_job = new_job.into_inner();
+    if new_job.name.is_empty() {
+        return Err(error(Status::UnprocessableEntity, "name can't be empty"));
+    }
+    let mut manager = manager::Manager::from_save_file();
+    if manager.data.jobs.iter().any(|j| j.name == new_job.name) {
+        return Err(error(
+            Status::Conflict,
+            format!("A job named {} already exists", new_job.name),
+        ));
+    }
+    let mut job = Job::new(
+        new_job.name,
+        new_job.path,
+        new_job.sync_to_google,
+        new_job.sync_interval,
+    );
+    if let Some(command) = new_job.command {
+        job.command = command;
+    }
+    if let Some(timeout) = new_job.timeout {
+        job.timeout = timeout;
+    }
+    if let Some(retry) = new_job.retry {
+        job.retry = retry;
+    }
+    if let Some(removed_policy) = new_job.removed_policy {
+        job.removed_policy = removed_policy;
+    }
+    job.caldav = new_job.caldav.filter(|caldav| !caldav.url.is_empty());
+    if let Some(schedule) = new_job.schedule {
+        let schedule = schedule.normalize();
+        schedule
+            .validate()
+            .map_err(|e| error(Status::UnprocessableEntity, e))?;
+        job.schedule = schedule;
+    }
+    manager.add_job(job.clone());
+    manager.save_job(&job).map_err(storage_error)?;
+    crate::scheduler::wake();
+    Ok(Created::new(format!("/api/v1/jobs/{}", job.id)).body(Json(job.redacted())))
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    request_body = JobPatch,
+    responses(
+        (status = 200, body = Job),
+        (status = 404, body = ErrorBody),
+        (status = 422, body = ErrorBody)
+    )
+)]
+#[patch("/jobs/<id>", data = "<patch>")]
+fn patch_job(id: String, patch: Json<JobPatch>) -> ApiResult<Json<Job>> {
+    let mut manager = manager::Manager::from_save_file();
+    let job = match manager.data.jobs.iter_mut().find(|j| j.id == id) {
+        Some(v) => v,
+        None => return Err(not_found("Job")),
+    };
+    patch
+        .into_inner()
+        .apply(job)
+        .map_err(|e| error(Status::UnprocessableEntity, e))?;
+    let job = job.clone();
+    manager.save_job(&job).map_err(storage_error)?;
+    crate::scheduler::wake();
+    Ok(Json(job.redacted()))
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 204),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[delete("/jobs/<id>")]
+fn delete_job(id: String) -> ApiResult<NoContent> {
+    let mut manager = manager::Manager::from_save_file();
+    let job = match manager.get_job_by_id(&id) {
+        Some(v) => v.clone(),
+        None => return Err(not_found("Job")),
+    };
+    manager.delete_job(&job.name).map_err(storage_error)?;
+    Ok(NoContent)
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 202, body = Job),
+        (status = 404, body = ErrorBody)
+    )
+)]
+/// Schedules the job for the next scheduler pass.
+#[post("/jobs/<id>/run")]
+fn run_job(id: String) -> ApiResult<Custom<Json<Job>>> {
+    let mut manager = manager::Manager::from_save_file();
+    let job = match manager.data.jobs.iter_mut().find(|j| j.id == id) {
+        Some(v) => v,
+        None => return Err(not_found("Job")),
+    };
+    job.next_sync = 0;
+    let job = job.clone();
+    manager.save_job(&job).map_err(storage_error)?;
+    crate::scheduler::wake();
+    Ok(Custom(Status::Accepted, Json(job.redacted())))
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 200, body = SyncPreview),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "The job is running", body = ErrorBody)
+    )
+)]
+/// Runs the job once and returns what syncing it would change, without saving anything or pushing
+/// to the calendar. The run shows up under the job's `progress` and can be cancelled like a real
+/// one.
+#[post("/jobs/<id>/dry_run")]
+async fn dry_run_job(id: String) -> ApiResult<Json<SyncPreview>> {
+    let manager = manager::Manager::read_no_save();
+    let job = match manager.get_job_by_id(&id) {
+        Some(job) => job.clone(),
+        None => return Err(not_found("Job")),
+    };
+    if crate::runner::is_running(&id) {
+        return Err(error(Status::Conflict, "Job is already running"));
+    }
+    Ok(Json(job.dry_run(&manager).await))
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 202, description = "The run is being stopped"),
+        (status = 409, description = "The job isn't running", body = ErrorBody)
+    )
+)]
+/// Stops the job's current run, or dry run.
+#[post("/jobs/<id>/cancel")]
+fn cancel_job(id: String) -> ApiResult<Status> {
+    if crate::runner::cancel(&id) {
+        Ok(Status::Accepted)
+    } else {
+        Err(error(Status::Conflict, "Job is not running"))
+    }
+}
+
+#[utoipa::path(
+    tag = "jobs",
+    responses(
+        (status = 200, body = Progress),
+        (status = 404, description = "The job isn't running", body = ErrorBody)
+    )
+)]
+/// What the job's current run has reported so far.
+#[get("/jobs/<id>/progress")]
+fn job_progress(id: String) -> ApiResult<Json<Progress>> {
+    match crate::runner::progress(&id) {
+        Some(progress) => Ok(Json(progress)),
+        None => Err(error(Status::NotFound, "Job is not running")),
+    }
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, body = Health))
+)]
+/// Whether the scheduler is running and which jobs are failing.
+#[get("/health")]
+fn health() -> Json<Health> {
+    Json(crate::health::get(&manager::Manager::read_no_save()))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "calendarwebthing", description = "JSON interface to assignments and jobs"),
+    servers((url = "/api/v1")),
+    paths(
+        list_assignments,
+        get_assignment,
+        create_assignment,
+        patch_assignment,
+        delete_assignment,
+        mark_assignment_done,
+        mark_assignment_undone,
+        list_jobs,
+        get_job,
+        list_job_runs,
+        create_job,
+        patch_job,
+        delete_job,
+        run_job,
+        dry_run_job,
+        cancel_job,
+        job_progress,
+        health
+    ),
+    components(schemas(ScriptAssignment, ScriptOutput, Message))
+)]
+pub struct ApiDoc;
+
+#[get("/openapi.json")]
+fn openapi() -> rocket::response::content::RawJson<String> {
+    rocket::response::content::RawJson(ApiDoc::openapi().to_pretty_json().unwrap())
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, _req: &rocket::Request) -> ApiError {
+    error(status, status.reason_lossy())
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        list_assignments,
+        get_assignment,
+        create_assignment,
+        patch_assignment,
+        delete_assignment,
+        mark_assignment_done,
+        mark_assignment_undone,
+        list_jobs,
+        get_job,
+        list_job_runs,
+        create_job,
+        patch_job,
+        delete_job,
+        run_job,
+        dry_run_job,
+        cancel_job,
+        job_progress,
+        health,
+        openapi
+    ]
+}
+
+pub fn get_catchers() -> Vec<rocket::Catcher> {
+    catchers![default_catcher]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// `/assignments/<id>?<course>` -> `/assignments/{id}`
+    fn to_openapi_path(uri: &str) -> String {
+        uri.split('?')
+            .next()
+            .unwrap()
+            .replace('<', "{")
+            .replace('>', "}")
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let doc = ApiDoc::openapi();
+        let mut documented: Vec<(String, String)> = Vec::new();
+        for (path, item) in &doc.paths.paths {
+            let operations = [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+                ("PUT", &item.put),
+            ];
+            for (method, operation) in operations {
+                if operation.is_some() {
+                    documented.push((method.to_string(), path.clone()));
+                }
+            }
+        }
+        let mut mounted: Vec<(String, String)> = get_routes()
+            .iter()
+            .filter(|route| route.name.as_deref() != Some("openapi"))
+            .map(|route| {
+                (
+                    route.method.to_string(),
+                    to_openapi_path(&route.uri.to_string()),
+                )
+            })
+            .collect();
+        documented.sort();
+        mounted.sort();
+        assert_eq!(documented, mounted);
+    }
+
+    /// `$ref`s point into the generated document's schemas.
+    fn resolve<'a>(schemas: &'a Value, schema: &'a Value) -> &'a Value {
+        match schema["$ref"].as_str() {
+            Some(path) => &schemas[path.trim_start_matches("#/components/schemas/")],
+            None => schema,
+        }
+    }
+
+    fn type_of(value: &Value) -> &'static str {
+        match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(n) if n.is_f64() => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// Checks `value` against `schema`: the type of everything in it, that every required
+    /// property is there and that it has exactly the documented properties.
+    fn check(schemas: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+        let schema = resolve(schemas, schema);
+        if let Some(variants) = schema["oneOf"].as_array().or(schema["anyOf"].as_array()) {
+            let errors: Vec<String> = variants
+                .iter()
+                .filter_map(|variant| check(schemas, variant, value, at).err())
+                .collect();
+            if errors.len() == variants.len() {
+                return Err(errors.join(", and "));
+            }
+            return Ok(());
+        }
+        if let Some(parts) = schema["allOf"].as_array() {
+            for part in parts {
+                check(schemas, part, value, at)?;
+            }
+            return Ok(());
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let actual = type_of(value);
+        // integers are numbers too
+        let number = actual == "integer" && types.contains(&"number");
+        if !types.is_empty() && !types.contains(&actual) && !number {
+            return Err(format!("{} is {} instead of {:?}", at, actual, types));
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return Err(format!(
+                    "{} is {} instead of one of {:?}",
+                    at, value, values
+                ));
+            }
+        }
+        match value {
+            Value::Object(map) => {
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().unwrap();
+                    if !map.contains_key(required) {
+                        return Err(format!("{}.{} is required but missing", at, required));
+                    }
+                }
+                let properties = schema["properties"].as_object();
+                for key in properties.into_iter().flat_map(|p| p.keys()) {
+                    if !map.contains_key(key) {
+                        return Err(format!("{}.{} is documented but not serialized", at, key));
+                    }
+                }
+                for (key, item) in map {
+                    let at = format!("{}.{}", at, key);
+                    match properties.and_then(|p| p.get(key)) {
+                        Some(property) => check(schemas, property, item, &at)?,
+                        None if schema["additionalProperties"].is_object() => {
+                            check(schemas, &schema["additionalProperties"], item, &at)?
+                        }
+                        None => return Err(format!("{} is serialized but not documented", at)),
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    check(schemas, &schema["items"], item, &format!("{}[{}]", at, i))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The published schema has to match what serde writes, and list as required exactly the
+    /// fields serde can't read `T` without.
+    fn assert_schema_matches<T: Serialize + DeserializeOwned>(name: &str, value: T) {
+        let doc = ApiDoc::openapi();
+        let schemas = serde_json::to_value(&doc.components.unwrap().schemas).unwrap();
+        assert!(schemas.get(name).is_some(), "{} isn't documented", name);
+        let value = serde_json::to_value(value).unwrap();
+        if let Err(e) = check(&schemas, &schemas[name], &value, name) {
+            panic!("{} schema drifted: {}", name, e);
+        }
+
+        let required: Vec<&str> = schemas[name]["required"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        for key in value.as_object().unwrap().keys() {
+            let mut without = value.clone();
+            without.as_object_mut().unwrap().remove(key);
+            let optional = serde_json::from_value::<T>(without).is_ok();
+            assert_eq!(
+                required.contains(&key.as_str()),
+                !optional,
+                "{}.{} is {} in the schema but {} for serde",
+                name,
+                key,
+                if optional { "required" } else { "optional" },
+                if optional { "optional" } else { "required" },
+            );
+        }
+    }
+
+    /// One value per schema with every optional field filled in, so nested schemas get checked
+    /// too.
+    fn filled_assignment() -> Assignment {
+        let mut assignment = Assignment::new("course".into(), 0, "name".into(), "job".into());
+        assignment.external_id = Some("42".into());
+        assignment.url = Some("https://example.com/42".into());
+        assignment.description = Some("Read chapter 4".into());
+        assignment.points = Some(12.5);
+        assignment.category = Some("homework".into());
+        assignment.synced = Some(true);
+        assignment.completed_at = Some(10);
+        assignment.archived_at = Some(20);
+        assignment.removed_upstream = Some(30);
+        assignment.remote_events.insert(
+            "google".into(),
+            crate::calendar::RemoteEvent {
+                id: "event".into(),
+                etag: Some("etag".into()),
+            },
+        );
+        assignment
+    }
+
+    fn filled_job() -> Job {
+        let mut job = Job::new("job".into(), "/tmp".into(), false, 60);
+        job.command = JobCommand {
+            program: "python3".into(),
+            args: vec!["scrape.py".into()],
+            working_dir: Some("/tmp".into()),
+            env: [("TOKEN".to_string(), "secret".to_string())].into(),
+            stdin: Some("{}".into()),
+        };
+        job.schedule = Schedule {
+            cron: Some("0 7-22 * * 1-5".into()),
+            window_start: Some("07:00".into()),
+            window_end: Some("22:00".into()),
+            weekdays: vec![1, 2, 3, 4, 5],
+        };
+        job.caldav = Some(CalDavConfig {
+            url: "https://dav.example.com/".into(),
+            collection: "calendars/alice/school/".into(),
+            username: "alice".into(),
+            password: Some("hunter2".into()),
+            password_file: Some("/run/secrets/caldav".into()),
+            component: crate::config::CalDavComponent::Todo,
+        });
+        job.last_status = Some(manager::RunStatus::Failed);
+        job.last_output = vec!["42".into()];
+        job
+    }
+
+    fn filled_script_assignment() -> ScriptAssignment {
+        ScriptAssignment {
+            course: "course".into(),
+            due: 0,
+            name: "name".into(),
+            external_id: Some("42".into()),
+            url: Some("https://example.com/42".into()),
+            description: Some("Read chapter 4".into()),
+            points: Some(12.5),
+            category: Some("quiz".into()),
+            submitted: true,
+            all_day: true,
+        }
+    }
+
+    fn filled_new_assignment() -> NewAssignment {
+        NewAssignment {
+            name: "name".into(),
+            course: "course".into(),
+            due: 0,
+            job_name: Some("job".into()),
+        }
+    }
+
+    fn filled_assignment_patch() -> AssignmentPatch {
+        AssignmentPatch {
+            name: Some("name".into()),
+            course: Some("course".into()),
+            due: Some(0),
+            done: Some(true),
+            dismissed: Some(false),
+        }
+    }
+
+    fn filled_new_job() -> NewJob {
+        let job = filled_job();
+        NewJob {
+            name: job.name,
+            path: "/tmp".into(),
+            sync_to_google: true,
+            sync_interval: 60,
+            command: Some(job.command),
+            timeout: Some(0),
+            retry: Some(job.retry),
+            schedule: Some(job.schedule),
+            removed_policy: Some(RemovedPolicy::Delete),
+            caldav: job.caldav,
+        }
+    }
+
+    fn filled_job_patch() -> JobPatch {
+        let job = filled_job();
+        JobPatch {
+            path: Some("/tmp".into()),
+            sync_to_google: Some(true),
+            sync_interval: Some(60),
+            command: Some(job.command),
+            timeout: Some(300),
+            retry: Some(job.retry),
+            schedule: Some(job.schedule),
+            removed_policy: Some(RemovedPolicy::Keep),
+            caldav: job.caldav,
+            paused: Some(false),
+        }
+    }
+
+    fn filled_run() -> JobRun {
+        let mut run = JobRun::new("job", 0);
+        run.exit_code = Some(1);
+        run.error = Some("Script failed".into());
+        run
+    }
+
+    fn filled_preview() -> SyncPreview {
+        SyncPreview {
+            run: filled_run(),
+            new: vec![filled_assignment()],
+            changed: vec![manager::DueChange {
+                assignment: filled_assignment(),
+                previous_due: 0,
+            }],
+            unchanged: vec![filled_assignment()],
+            disappeared: vec![filled_assignment()],
+        }
+    }
+
+    fn filled_progress() -> Progress {
+        Progress {
+            current: Some(1),
+            total: Some(3),
+            message: Some("Logging in".into()),
+            assignments: 2,
+            log: ["started".to_string()].into(),
+        }
+    }
+
+    fn filled_health() -> Health {
+        Health {
+            ok: false,
+            last_tick: Some(0),
+            scheduler_error: Some(crate::health::SchedulerError {
+                at: 0,
+                message: "Panicked".into(),
+            }),
+            failing_jobs: vec![crate::health::FailingJob {
+                id: "id".into(),
+                name: "job".into(),
+                status: manager::RunStatus::TimedOut,
+                error: Some("Timed out".into()),
+                at: Some(0),
+            }],
+        }
+    }
+
+    #[test]
+    fn patching_a_redacted_caldav_keeps_the_password() {
+        let mut job = filled_job();
+        // what a client gets back from the API, then sends back with a new collection
+        let read: Job =
+            serde_json::from_value(serde_json::to_value(job.clone().redacted()).unwrap()).unwrap();
+        let mut caldav = read.caldav.unwrap();
+        assert_eq!(caldav.password, None);
+        caldav.collection = "calendars/alice/work/".into();
+        let patch: JobPatch =
+            serde_json::from_value(serde_json::json!({ "caldav": caldav })).unwrap();
+        patch.apply(&mut job).unwrap();
+
+        let caldav = job.caldav.unwrap();
+        assert_eq!(caldav.collection, "calendars/alice/work/");
+        assert_eq!(caldav.password.as_deref(), Some("hunter2"));
+        assert_eq!(
+            caldav.password_file,
+            Some(std::path::PathBuf::from("/run/secrets/caldav"))
+        );
+    }
+
+    #[test]
+    fn schemas_match_serialized_types() {
+        assert_schema_matches(
+            "Assignment",
+            Assignment::new("course".into(), 0, "name".into(), "job".into()),
+        );
+        assert_schema_matches("Assignment", filled_assignment());
+        assert_schema_matches("Job", Job::new("job".into(), "/tmp".into(), false, 60));
+        assert_schema_matches("Job", filled_job());
+        assert_schema_matches(
+            "ScriptAssignment",
+            ScriptAssignment {
+                course: "course".into(),
+                due: 0,
+                name: "name".into(),
+                ..Default::default()
+            },
+        );
+        assert_schema_matches("ScriptAssignment", filled_script_assignment());
+        assert_schema_matches(
+            "NewAssignment",
+            NewAssignment {
+                job_name: None,
+                ..filled_new_assignment()
+            },
+        );
+        assert_schema_matches("NewAssignment", filled_new_assignment());
+        assert_schema_matches(
+            "NewJob",
+            NewJob {
+                name: "job".into(),
+                path: "/tmp".into(),
+                sync_to_google: true,
+                sync_interval: 60,
+                command: None,
+                timeout: None,
+                retry: None,
+                schedule: None,
+                removed_policy: None,
+                caldav: None,
+            },
+        );
+        assert_schema_matches("NewJob", filled_new_job());
+        assert_schema_matches("AssignmentPatch", AssignmentPatch::default());
+        assert_schema_matches("AssignmentPatch", filled_assignment_patch());
+        assert_schema_matches("JobPatch", JobPatch::default());
+        assert_schema_matches("JobPatch", filled_job_patch());
+        assert_schema_matches("JobRun", JobRun::new("job", 0));
+        assert_schema_matches("JobRun", filled_run());
+        assert_schema_matches("SyncPreview", filled_preview());
+        assert_schema_matches("Health", Health::default());
+        assert_schema_matches("Health", filled_health());
+        assert_schema_matches("Progress", Progress::default());
+        assert_schema_matches("Progress", filled_progress());
+    }
+
+    /// A handler, whatever it takes. `Args` is what it takes and `R` what it answers with.
+    trait Handler<Args, R> {}
+
+    macro_rules! handler {
+        ($($arg:ident),*) => {
+            impl<F: Fn($($arg),*) -> R, $($arg,)* R> Handler<($($arg,)*), R> for F {}
+        };
+    }
+    handler!();
+    handler!(A);
+    handler!(A, B);
+    handler!(A, B, C, D, E, G);
+
+    /// The JSON in a handler's answer.
+    trait JsonBody {
+        type Value: Serialize;
+    }
+    impl<T: Serialize> JsonBody for Json<T> {
+        type Value = T;
+    }
+    impl<T: JsonBody> JsonBody for Created<T> {
+        type Value = T::Value;
+    }
+    impl<T: JsonBody> JsonBody for Custom<T> {
+        type Value = T::Value;
+    }
+    impl<T: JsonBody> JsonBody for ApiResult<T> {
+        type Value = T::Value;
+    }
+
+    /// Answers without a body.
+    trait NoBody {}
+    impl NoBody for NoContent {}
+    impl NoBody for Status {}
+    impl<T: NoBody> NoBody for ApiResult<T> {}
+
+    /// The JSON in a handler's request.
+    trait JsonRequest {
+        type Value: Serialize;
+    }
+    impl<T: Serialize> JsonRequest for (Json<T>,) {
+        type Value = T;
+    }
+    impl<T: Serialize> JsonRequest for (String, Json<T>) {
+        type Value = T;
+    }
+
+    /// `sample` has to be what `handler` reads, or this doesn't compile.
+    fn request<Args: JsonRequest, R>(
+        _handler: impl Handler<Args, R>,
+        sample: Args::Value,
+    ) -> Value {
+        serde_json::to_value(sample).unwrap()
+    }
+
+    /// `sample` has to be what `handler` answers with, or this doesn't compile.
+    fn response<Args, R: JsonBody>(_handler: impl Handler<Args, R>, sample: R::Value) -> Value {
+        serde_json::to_value(sample).unwrap()
+    }
+
+    fn async_response<Args, R: std::future::Future>(
+        _handler: impl Handler<Args, R>,
+        sample: <R::Output as JsonBody>::Value,
+    ) -> Value
+    where
+        R::Output: JsonBody,
+    {
+        serde_json::to_value(sample).unwrap()
+    }
+
+    fn no_body<Args, R: NoBody>(_handler: impl Handler<Args, R>) -> Value {
+        Value::Null
+    }
+
+    /// The `request_body` and `body` each route documents have to fit what its handler really
+    /// reads and answers with, so pointing one at the wrong schema fails here.
+    #[test]
+    fn route_bodies_match_their_handlers() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = &doc["components"]["schemas"];
+        // method, path, request, response
+        let routes = [
+            (
+                "get",
+                "/assignments",
+                Value::Null,
+                response(list_assignments, vec![filled_assignment()]),
+            ),
+            (
+                "get",
+                "/assignments/{id}",
+                Value::Null,
+                response(get_assignment, filled_assignment()),
+            ),
+            (
+                "post",
+                "/assignments",
+                request(create_assignment, filled_new_assignment()),
+                response(create_assignment, filled_assignment()),
+            ),
+            (
+                "patch",
+                "/assignments/{id}",
+                request(patch_assignment, filled_assignment_patch()),
+                response(patch_assignment, filled_assignment()),
+            ),
+            (
+                "delete",
+                "/assignments/{id}",
+                Value::Null,
+                no_body(delete_assignment),
+            ),
+            (
+                "post",
+                "/assignments/{id}/done",
+                Value::Null,
+                response(mark_assignment_done, filled_assignment()),
+            ),
+            (
+                "delete",
+                "/assignments/{id}/done",
+                Value::Null,
+                response(mark_assignment_undone, filled_assignment()),
+            ),
+            (
+                "get",
+                "/jobs",
+                Value::Null,
+                response(list_jobs, vec![filled_job()]),
+            ),
+            (
+                "get",
+                "/jobs/{id}",
+                Value::Null,
+                response(get_job, filled_job()),
+            ),
+            (
+                "get",
+                "/jobs/{id}/runs",
+                Value::Null,
+                response(list_job_runs, vec![filled_run()]),
+            ),
+            (
+                "post",
+                "/jobs",
+                request(create_job, filled_new_job()),
+                response(create_job, filled_job()),
+            ),
+            (
+                "patch",
+                "/jobs/{id}",
+                request(patch_job, filled_job_patch()),
+                response(patch_job, filled_job()),
+            ),
+            ("delete", "/jobs/{id}", Value::Null, no_body(delete_job)),
+            (
+                "post",
+                "/jobs/{id}/run",
+                Value::Null,
+                response(run_job, filled_job()),
+            ),
+            (
+                "post",
+                "/jobs/{id}/dry_run",
+                Value::Null,
+                async_response(dry_run_job, filled_preview()),
+            ),
+            (
+                "post",
+                "/jobs/{id}/cancel",
+                Value::Null,
+                no_body(cancel_job),
+            ),
+            (
+                "get",
+                "/jobs/{id}/progress",
+                Value::Null,
+                response(job_progress, filled_progress()),
+            ),
+            (
+                "get",
+                "/health",
+                Value::Null,
+                response(health, filled_health()),
+            ),
+        ];
+
+        let mut operations = 0;
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                operations += 1;
+                let (_, _, request, response) = routes
+                    .iter()
+                    .find(|(m, p, _, _)| m == method && p == path)
+                    .unwrap_or_else(|| panic!("{} {} has no sample bodies", method, path));
+                let request_schema =
+                    &operation["requestBody"]["content"]["application/json"]["schema"];
+                let response_schema = operation["responses"]
+                    .as_object()
+                    .unwrap()
+                    .iter()
+                    .find(|(status, _)| status.starts_with('2'))
+                    .map(|(_, r)| &r["content"]["application/json"]["schema"])
+                    .unwrap_or(&Value::Null);
+                for (what, schema, sample) in [
+                    ("request", request_schema, request),
+                    ("response", response_schema, response),
+                ] {
+                    let at = format!("{} {} {}", method, path, what);
+                    match (schema, sample) {
+                        (Value::Null, Value::Null) => {}
+                        (Value::Null, _) => panic!("{} isn't documented", at),
+                        (_, Value::Null) => panic!("{} is documented but has no body", at),
+                        (schema, sample) => {
+                            if let Err(e) = check(schemas, schema, sample, &at) {
+                                panic!("{} drifted from its handler: {}", at, e);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(operations, routes.len());
+    }
+}
@@ -9,6 +9,8 @@ struct AssignmentPageData {
     manager: manager::ManagerData,
     job: Option<manager::Job>,
     assignment: manager::Assignment,
+    /// The push still waiting for this assignment, if any.
+    outbox: Option<crate::outbox::OutboxEntry>,
     htmx_request: bool,
 }
 
@@ -47,8 +49,16 @@ fn new_assignment(
         new_assignment.name.clone(),
         "manual".to_string(),
     );
-    manager.add_assignment(assignment);
-    match manager.save() {
+    manager.add_assignment(assignment.clone());
+    // add_assignment keeps the id of an assignment that already matches
+    let assignment = manager
+        .data
+        .assignments
+        .iter()
+        .find(|a| **a == assignment)
+        .unwrap()
+        .clone();
+    match manager.save_assignment(&assignment) {
         Ok(_) => {}
         Err(e) => {
             return Err(rocket::response::status::BadRequest(e.to_string()));
@@ -58,26 +68,21 @@ fn new_assignment(
     return Ok(rocket::response::Redirect::to("/assignments"));
     //     }
 }
+#[derive(FromForm, Debug)]
+struct EditAssignment {
+    id: String,
+    name: String,
+    due: String,
+    course: String,
+}
+
 #[post("/edit", data = "<assignment>")]
-fn edit_job(assignment: rocket::form::Form<NewAssignment>) -> rocket::response::Redirect {
+fn edit_job(assignment: rocket::form::Form<EditAssignment>) -> rocket::response::Redirect {
     let mut manager = manager::Manager::from_save_file();
-    if !manager
-        .data
-        .assignments
-        .iter()
-        .any(|j| j.name == assignment.name)
-    {
-        return rocket::response::Redirect::to(format!(
-            "/{}",
-            manager::Assignment::get_link_name(assignment.name.clone())
-        ));
-    }
-    let current_assignment = manager
-        .data
-        .assignments
-        .iter_mut()
-        .find(|j| j.name == assignment.name)
-        .unwrap();
+    let mut current_assignment = match manager.get_assignment(&assignment.id) {
+        Some(v) => v.clone(),
+        None => return rocket::response::Redirect::to("/assignments"),
+    };
     let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]").unwrap();
     let due = assignment.due.replace("T", " ");
     let date = rocket::time::PrimitiveDateTime::parse(&due, &format);
@@ -86,25 +91,34 @@ fn edit_job(assignment: rocket::form::Form<NewAssignment>) -> rocket::response::
         Err(e) => {
             println!("{:?}, {}", e, due);
             return rocket::response::Redirect::to(format!(
-                "/{}",
-                manager::Assignment::get_link_name(assignment.name.clone())
+                "/assignments/{}/view",
+                current_assignment.id
             ));
         }
     };
     let d = date.assume_utc().unix_timestamp();
     current_assignment.due = d as u64;
+    current_assignment.name = assignment.name.clone();
+    current_assignment.link_name = manager::Assignment::get_link_name(assignment.name.clone());
     current_assignment.course = assignment.course.clone();
-    manager.save().unwrap();
+    // pushes the change to the calendar event
+    current_assignment.synced = None;
+    for a in manager.data.assignments.iter_mut() {
+        if a.id == current_assignment.id {
+            *a = current_assignment.clone();
+        }
+    }
+    manager.save_assignment(&current_assignment).unwrap();
     rocket::response::Redirect::to(format!("/assignments"))
 }
-#[delete("/<name>/delete")]
-fn delete_assignment(name: String) -> rocket::response::Redirect {
+#[delete("/<id>/delete")]
+fn delete_assignment(id: String) -> rocket::response::Redirect {
     let mut manager = manager::Manager::from_save_file();
-    manager
-        .data
-        .assignments
-        .retain(|assignment| assignment.link_name != name);
-    manager.save().unwrap();
+    if let Some(assignment) = manager.get_assignment(&id).cloned() {
+        manager.delete_assignment(&assignment).unwrap();
+        // to take it off the calendar
+        crate::scheduler::wake();
+    }
     rocket::response::Redirect::to("/assignments")
 }
 
@@ -113,15 +127,24 @@ fn get_page_assignment(
     id: String,
     name: String,
     headers: Headers,
-) -> Result<Template, rocket::response::status::NotFound<String>> {
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
     println!("{}", name);
     let manager = manager::Manager::read_no_save();
-    let assignment = match manager.get_assignment_from_link_name(id) {
+    let assignment = match manager.get_assignment(&id) {
         Some(assignment) => assignment,
         None => {
+            // old links used the link name instead of the id
+            if let Some(assignment) = manager.get_assignment_from_link_name(id) {
+                return Ok(PageResponse::Redirect(
+                    rocket::response::Redirect::permanent(format!(
+                        "/assignments/{}/{}",
+                        assignment.id, name
+                    )),
+                ));
+            }
             return Err(rocket::response::status::NotFound(
                 "Assignment not found".to_string(),
-            ))
+            ));
         }
     };
     let page_data = AssignmentPageData {
@@ -132,18 +155,180 @@ fn get_page_assignment(
             .iter()
             .find(|job| job.name == assignment.clone().job_name.unwrap_or("".to_string()))
         {
-            Some(job) => Some(job.clone()),
+            Some(job) => Some(job.clone().redacted()),
             None => None,
         },
-        manager,
+        outbox: crate::outbox::get_entry(&manager, &assignment.id).cloned(),
+        manager: manager.redacted(),
         htmx_request: headers.contains("hx-request".to_string()),
     };
     if headers.contains("hx-request".to_string()) {
         let page = page_data.page.clone();
         println!("Rendering page: {}", page);
-        Ok(Template::render(page, context! {page_data}))
+        Ok(PageResponse::Page(Template::render(
+            page,
+            context! {page_data},
+        )))
+    } else {
+        Ok(PageResponse::Page(Template::render(
+            "layout",
+            context! {page_data},
+        )))
+    }
+}
+
+/// Applies `change` to one assignment and saves it. Htmx requests get the updated view back,
+/// everything else is sent back to the list.
+fn update_assignment(
+    id: String,
+    headers: Headers,
+    change: impl FnOnce(&mut manager::Manager, &str),
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
+    let mut manager = manager::Manager::from_save_file();
+    if manager.get_assignment(&id).is_none() {
+        return Err(rocket::response::status::NotFound(
+            "Assignment not found".to_string(),
+        ));
+    }
+    change(&mut manager, &id);
+    let assignment = manager.get_assignment(&id).unwrap().clone();
+    manager.save_assignment(&assignment).unwrap();
+    manager.break_lock();
+    // to push it to the calendar
+    crate::scheduler::wake();
+    if !headers.contains("hx-request".to_string()) {
+        return Ok(PageResponse::Redirect(rocket::response::Redirect::to(
+            "/assignments",
+        )));
+    }
+    // htmx swaps the assignment in place, so send back its updated view
+    let page_data = AssignmentPageData {
+        page: "assignments/view".to_string(),
+        job: manager
+            .data
+            .jobs
+            .iter()
+            .find(|job| Some(&job.name) == assignment.job_name.as_ref())
+            .cloned()
+            .map(manager::Job::redacted),
+        outbox: crate::outbox::get_entry(&manager.data, &assignment.id).cloned(),
+        assignment,
+        manager: manager.data.redacted(),
+        htmx_request: true,
+    };
+    Ok(PageResponse::Page(Template::render(
+        "assignments/view",
+        context! {page_data},
+    )))
+}
+
+#[post("/<id>/done")]
+fn mark_done(
+    id: String,
+    headers: Headers,
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
+    update_assignment(id, headers, |manager, id| manager.mark_done(id))
+}
+
+#[post("/<id>/undone")]
+fn mark_undone(
+    id: String,
+    headers: Headers,
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
+    update_assignment(id, headers, |manager, id| manager.mark_undone(id))
+}
+
+/// Takes an overdue assignment off the main list without marking it done.
+#[post("/<id>/dismiss")]
+fn dismiss(
+    id: String,
+    headers: Headers,
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
+    update_assignment(id, headers, |manager, id| manager.dismiss(id, true))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ArchivePageData {
+    page: String,
+    manager: manager::ManagerData,
+    assignments: Vec<manager::Assignment>,
+    q: String,
+    htmx_request: bool,
+}
+
+/// Past due assignments, newest first. `q` matches the name or course, ignoring case.
+#[get("/archive?<q>")]
+fn archive(q: Option<String>, headers: Headers) -> Template {
+    let manager = manager::Manager::read_no_save();
+    let q = q.unwrap_or_default();
+    let needle = q.to_lowercase();
+    let mut assignments: Vec<manager::Assignment> = manager
+        .assignments
+        .iter()
+        .filter(|a| a.archived_at.is_some())
+        .filter(|a| {
+            a.name.to_lowercase().contains(&needle) || a.course.to_lowercase().contains(&needle)
+        })
+        .cloned()
+        .collect();
+    assignments.sort_by_key(|a| std::cmp::Reverse(a.due));
+    let page_data = ArchivePageData {
+        page: "assignments/archive".to_string(),
+        manager: manager.redacted(),
+        assignments,
+        q,
+        htmx_request: headers.contains("hx-request".to_string()),
+    };
+    if page_data.htmx_request {
+        Template::render(page_data.page.clone(), context! {page_data})
+    } else {
+        Template::render("layout", context! {page_data})
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CompletedPageData {
+    page: String,
+    manager: manager::ManagerData,
+    assignments: Vec<manager::Assignment>,
+    courses: Vec<String>,
+    course: Option<String>,
+    htmx_request: bool,
+}
+
+/// Done assignments, most recently completed first.
+#[get("/completed?<course>")]
+fn completed(course: Option<String>, headers: Headers) -> Template {
+    let manager = manager::Manager::read_no_save();
+    let mut courses: Vec<String> = manager
+        .assignments
+        .iter()
+        .filter(|a| a.done)
+        .map(|a| a.course.clone())
+        .collect();
+    courses.sort();
+    courses.dedup();
+    let course = course.filter(|c| !c.is_empty());
+    let mut assignments: Vec<manager::Assignment> = manager
+        .assignments
+        .iter()
+        .filter(|a| a.done)
+        .filter(|a| course.as_ref().is_none_or(|c| a.course == *c))
+        .cloned()
+        .collect();
+    assignments.sort_by_key(|a| std::cmp::Reverse(a.completed_at));
+    let page_data = CompletedPageData {
+        page: "assignments/completed".to_string(),
+        manager: manager.redacted(),
+        assignments,
+        courses,
+        course,
+        htmx_request: headers.contains("hx-request".to_string()),
+    };
+    if page_data.htmx_request {
+        Template::render(page_data.page.clone(), context! {page_data})
     } else {
-        Ok(Template::render("layout", context! {page_data}))
+        Template::render("layout", context! {page_data})
     }
 }
 
@@ -152,6 +337,11 @@ pub fn get_routes() -> Vec<Route> {
         get_page_assignment,
         new_assignment,
         delete_assignment,
-        edit_job
+        edit_job,
+        mark_done,
+        mark_undone,
+        dismiss,
+        completed,
+        archive
     ]
 }
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::config::{CalendarKind, Config};
+use crate::manager::{now, Assignment, Job};
+
+pub mod caldav;
+pub mod google;
+pub mod ical;
+pub mod mock;
+
+pub use caldav::CalDavCalendar;
+pub use google::GoogleCalendar;
+
+/// When an event happens. All day events are kept as local dates since that is what calendars
+/// store for them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventTime {
+    At(u64),
+    /// `YYYY-MM-DD`
+    Day(String),
+}
+
+/// A calendar event as far as syncing assignments cares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Set by the calendar once the event exists there.
+    pub id: Option<String>,
+    /// Changes whenever the event is changed, by us or anyone else.
+    pub etag: Option<String>,
+    pub summary: String,
+    pub description: Option<String>,
+    pub start: EventTime,
+    /// Exclusive, so an all day event ends on the day after.
+    pub end: EventTime,
+}
+
+impl Event {
+    pub fn for_assignment(assignment: &Assignment) -> Self {
+        let mut description = format!("Due for {}", assignment.course);
+        if let Some(text) = &assignment.description {
+            description.push_str("\n\n");
+            description.push_str(text);
+        }
+        if let Some(url) = &assignment.url {
+            description.push_str("\n\n");
+            description.push_str(url);
+        }
+        let (start, end) = if assignment.all_day {
+            (
+                EventTime::Day(crate::schedule::local_date(assignment.due)),
+                EventTime::Day(crate::schedule::local_date(assignment.due + 24 * 60 * 60)),
+            )
+        } else {
+            (EventTime::At(assignment.due), EventTime::At(assignment.due))
+        };
+        Self {
+            id: None,
+            etag: None,
+            summary: assignment.name.clone(),
+            description: Some(description),
+            start,
+            end,
+        }
+    }
+
+    /// Whether `self` needs changing to look like `wanted`. Ids and etags don't count.
+    fn differs_from(&self, wanted: &Event) -> bool {
+        self.summary != wanted.summary
+            || self.description != wanted.description
+            || self.start != wanted.start
+            || self.end != wanted.end
+    }
+}
+
+/// A calendar assignments get pushed to. Errors are `std::io::Error`s like everywhere else, with
+/// `NotFound` for events that don't exist (anymore).
+#[rocket::async_trait]
+pub trait CalendarBackend: std::fmt::Debug + Send + Sync {
+    /// Identifies the calendar in `Assignment::remote_events`. Has to stay the same between runs.
+    fn name(&self) -> &str;
+    /// Events that end at or after `from`.
+    async fn list(&self, from: u64) -> Result<Vec<Event>, std::io::Error>;
+    /// Returns the event as created, with its id set.
+    async fn create(&self, event: &Event) -> Result<Event, std::io::Error>;
+    /// Replaces the event with `event.id`. Calendars that can may use `event.etag` to notice
+    /// changes made there since.
+    async fn update(&self, event: &Event) -> Result<Event, std::io::Error>;
+    async fn delete(&self, id: &str) -> Result<(), std::io::Error>;
+}
+
+/// Where `job`'s assignments go: its own CalDAV calendar when it has one, otherwise the one
+/// picked in the config. Each backend is created once per process.
+pub fn backend_for(job: Option<&Job>) -> Arc<dyn CalendarBackend> {
+    static BACKENDS: LazyLock<Mutex<HashMap<String, Arc<dyn CalendarBackend>>>> =
+        LazyLock::new(|| Mutex::new(HashMap::new()));
+    let caldav = job.and_then(|job| job.caldav.as_ref());
+    // keyed by the whole config so a changed password gets a new backend
+    let key = caldav
+        .map(|config| serde_json::to_string(config).unwrap_or_default())
+        .unwrap_or_default();
+    let mut backends = BACKENDS.lock().unwrap();
+    backends
+        .entry(key)
+        .or_insert_with(|| match caldav {
+            Some(config) => Arc::new(CalDavCalendar::from_config(config)),
+            None => {
+                let config = Config::get();
+                match config.calendar {
+                    CalendarKind::Google => Arc::new(GoogleCalendar::from_config(&config.google)),
+                    CalendarKind::Mock => Arc::new(mock::backend(&mock::config())),
+                    CalendarKind::CalDav => Arc::new(CalDavCalendar::from_config(&config.caldav)),
+                }
+            }
+        })
+        .clone()
+}
+
+/// Turns error statuses into errors. Gone events count as not found.
+async fn check(response: Response) -> Result<Response, std::io::Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let kind = match status {
+        StatusCode::NOT_FOUND | StatusCode::GONE => std::io::ErrorKind::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => std::io::ErrorKind::PermissionDenied,
+        _ => std::io::ErrorKind::Other,
+    };
+    Err(std::io::Error::new(
+        kind,
+        format!("Calendar server returned {}: {}", status, body.trim()),
+    ))
+}
+
+/// Where an assignment ended up on a calendar.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct RemoteEvent {
+    pub id: String,
+    pub etag: Option<String>,
+}
+
+impl From<Event> for RemoteEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            id: event.id.unwrap_or_default(),
+            etag: event.etag,
+        }
+    }
+}
+
+/// Finds an event for an assignment that doesn't have one stored yet, and takes it out of
+/// `unclaimed` so no other assignment gets it too.
+async fn adopt(
+    backend: &dyn CalendarBackend,
+    assignment: &Assignment,
+    unclaimed: &mut Option<Vec<Event>>,
+) -> Result<Option<Event>, std::io::Error> {
+    if unclaimed.is_none() {
+        *unclaimed = Some(backend.list(now()).await?);
+    }
+    let events = unclaimed.as_mut().unwrap();
+    let description = format!("Due for {}", assignment.course);
+    let found = events.iter().position(|event| {
+        event.summary == assignment.name
+            && event
+                .description
+                .as_ref()
+                .is_some_and(|d| d.starts_with(&description))
+    });
+    Ok(found.map(|i| events.remove(i)))
+}
+
+/// Pushes one assignment and returns where it is on the calendar now, or `None` once it has
+/// been taken off. Done assignments are taken off. Assignments that were pushed before go
+/// straight to the event they made, others adopt an upcoming event with their name and course
+/// (as made by the old `google.js`) or get a new one. `unclaimed` is listed the first time an
+/// assignment needs it, pass the same one for a whole batch.
+pub async fn push(
+    backend: &dyn CalendarBackend,
+    assignment: &Assignment,
+    unclaimed: &mut Option<Vec<Event>>,
+) -> Result<Option<RemoteEvent>, std::io::Error> {
+    let mut wanted = Event::for_assignment(assignment);
+    let existing = match assignment.remote_events.get(backend.name()) {
+        Some(event) => Some(event.clone()),
+        None => match adopt(backend, assignment, unclaimed).await? {
+            Some(event) if !assignment.done && !event.differs_from(&wanted) => {
+                return Ok(Some(event.into()));
+            }
+            event => event.map(RemoteEvent::from),
+        },
+    };
+
+    if assignment.done {
+        if let Some(existing) = existing {
+            match backend.delete(&existing.id).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => println!("Removed {} from the calendar", assignment.name),
+            }
+        }
+        return Ok(None);
+    }
+    if let Some(existing) = existing {
+        wanted.id = Some(existing.id);
+        // lets the calendar tell when the event was changed there since
+        wanted.etag = existing.etag;
+        match backend.update(&wanted).await {
+            Ok(event) => {
+                println!("Updated {} on the calendar", assignment.name);
+                return Ok(Some(event.into()));
+            }
+            // deleted on the calendar, so it gets made again
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                wanted.id = None;
+                wanted.etag = None;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let event = backend.create(&wanted).await?;
+    println!("Added {} to the calendar", assignment.name);
+    Ok(Some(event.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CalDavComponent, CalDavConfig};
+
+    /// Serves the mock on a free port and returns a backend pointed at it. Each test gets its own
+    /// calendar since the mock's events are shared.
+    async fn mock_backend(calendar_id: &str) -> GoogleCalendar {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = rocket::Config {
+            port,
+            address: std::net::Ipv4Addr::LOCALHOST.into(),
+            log_level: rocket::config::LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+        let rocket = rocket::custom(config).mount(mock::MOUNT, mock::get_routes());
+        tokio::spawn(rocket.launch());
+        let mut config = mock::base_config(&format!("http://127.0.0.1:{}{}", port, mock::MOUNT));
+        config.calendar_id = calendar_id.to_string();
+        let backend = mock::backend(&config);
+        for _ in 0..50 {
+            if backend.list(0).await.is_ok() {
+                return backend;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("mock calendar didn't start");
+    }
+
+    /// Pushes the assignments and stores the events they got, like the manager does.
+    async fn push_all(backend: &dyn CalendarBackend, assignments: &mut [&mut Assignment]) {
+        let mut unclaimed = None;
+        for assignment in assignments {
+            match push(backend, assignment, &mut unclaimed).await.unwrap() {
+                Some(event) => assignment
+                    .remote_events
+                    .insert(backend.name().to_string(), event),
+                None => assignment.remote_events.remove(backend.name()),
+            };
+        }
+    }
+
+    #[rocket::async_test]
+    async fn sync_creates_updates_and_deletes_events() {
+        let backend = mock_backend("sync").await;
+        let due = now() + 24 * 60 * 60;
+        let mut first = Assignment::new("course".into(), due, "first".into(), "job".into());
+        let mut second = Assignment::new("course".into(), due, "second".into(), "job".into());
+        second.all_day = true;
+
+        push_all(&backend, &mut [&mut first, &mut second]).await;
+        let events = backend.list(now()).await.unwrap();
+        assert_eq!(events.len(), 2);
+        let event = events.iter().find(|e| e.summary == "second").unwrap();
+        assert_eq!(event.start, Event::for_assignment(&second).start);
+        assert!(matches!(event.start, EventTime::Day(_)));
+
+        first.due += 60 * 60;
+        push_all(&backend, &mut [&mut first]).await;
+        let events = backend.list(now()).await.unwrap();
+        let event = events.iter().find(|e| e.summary == "first").unwrap();
+        assert_eq!(event.start, EventTime::At(first.due));
+
+        first.mark_done();
+        push_all(&backend, &mut [&mut first]).await;
+        assert!(first.remote_events.is_empty());
+        let events = backend.list(now()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].summary, "second");
+    }
+
+    #[rocket::async_test]
+    async fn stored_events_survive_renames_and_shared_names() {
+        let backend = mock_backend("ids").await;
+        let due = now() + 24 * 60 * 60;
+        let mut math = Assignment::new("math".into(), due, "Homework 1".into(), "job".into());
+        let mut physics = Assignment::new("physics".into(), due, "Homework 1".into(), "job".into());
+        push_all(&backend, &mut [&mut math, &mut physics]).await;
+        assert_eq!(backend.list(now()).await.unwrap().len(), 2);
+        let id = math.remote_events[backend.name()].id.clone();
+
+        math.name = "Homework 1 (revised)".into();
+        physics.due += 60 * 60;
+        push_all(&backend, &mut [&mut math, &mut physics]).await;
+        let events = backend.list(now()).await.unwrap();
+        assert_eq!(events.len(), 2);
+        let renamed = events.iter().find(|e| e.id.as_ref() == Some(&id)).unwrap();
+        assert_eq!(renamed.summary, "Homework 1 (revised)");
+        assert_eq!(renamed.start, EventTime::At(due));
+        assert_eq!(math.remote_events[backend.name()].etag, renamed.etag);
+    }
+
+    #[rocket::async_test]
+    async fn events_changed_on_the_calendar_are_still_updated() {
+        let backend = mock_backend("etags").await;
+        let due = now() + 24 * 60 * 60;
+        let mut assignment = Assignment::new("math".into(), due, "Essay".into(), "job".into());
+        push_all(&backend, &mut [&mut assignment]).await;
+        let stored = assignment.remote_events[backend.name()].clone();
+
+        // someone moves it on the calendar, so the stored etag is stale
+        let mut moved = Event::for_assignment(&assignment);
+        moved.id = Some(stored.id.clone());
+        moved.start = EventTime::At(due + 60 * 60);
+        let moved = backend.update(&moved).await.unwrap();
+        assert_ne!(moved.etag, stored.etag);
+
+        assignment.name = "Essay (2000 words)".into();
+        push_all(&backend, &mut [&mut assignment]).await;
+        let events = backend.list(now()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].summary, "Essay (2000 words)");
+        assert_eq!(events[0].start, EventTime::At(due));
+        assert_eq!(
+            assignment.remote_events[backend.name()].etag,
+            events[0].etag
+        );
+
+        // already made to look right there, so it is left alone
+        let mut renamed = Event::for_assignment(&assignment);
+        renamed.id = Some(stored.id.clone());
+        renamed.summary = "Essay (2500 words)".into();
+        let renamed = backend.update(&renamed).await.unwrap();
+        assignment.name = "Essay (2500 words)".into();
+        push_all(&backend, &mut [&mut assignment]).await;
+        assert_eq!(assignment.remote_events[backend.name()].etag, renamed.etag);
+    }
+
+    #[rocket::async_test]
+    async fn old_events_are_adopted_by_name_and_course() {
+        let backend = mock_backend("adopt").await;
+        let due = now() + 24 * 60 * 60;
+        let mut assignment = Assignment::new("math".into(), due, "Quiz".into(), "job".into());
+        // made by google.js, which didn't store ids
+        let old = backend
+            .create(&Event::for_assignment(&assignment))
+            .await
+            .unwrap();
+        let mut other = Assignment::new("physics".into(), due, "Quiz".into(), "job".into());
+        push_all(&backend, &mut [&mut assignment, &mut other]).await;
+        assert_eq!(assignment.remote_events[backend.name()].id, old.id.unwrap());
+        assert_eq!(backend.list(now()).await.unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn list_follows_pages() {
+        let backend = mock_backend("pages").await;
+        let due = now() + 60 * 60;
+        for i in 0..300 {
+            let assignment = Assignment::new("course".into(), due, i.to_string(), "job".into());
+            backend
+                .create(&Event::for_assignment(&assignment))
+                .await
+                .unwrap();
+        }
+        assert_eq!(backend.list(now()).await.unwrap().len(), 300);
+    }
+
+    #[test]
+    fn caldav_events_round_trip() {
+        let due = 1_792_383_978;
+        let mut assignment = Assignment::new(
+            "math".into(),
+            due,
+            "Essay, part 1; draft".into(),
+            "job".into(),
+        );
+        assignment.description = Some(format!("Read chapter 3\n{}", "long line ".repeat(20)));
+        let event = Event::for_assignment(&assignment);
+        let text = ical::write("uid-1", CalDavComponent::Event, &event);
+        assert!(text.lines().all(|line| line.len() <= 75));
+        assert_eq!(ical::read(&text), Some(("uid-1".to_string(), event)));
+
+        assignment.all_day = true;
+        let event = Event::for_assignment(&assignment);
+        let text = ical::write("uid-2", CalDavComponent::Todo, &event);
+        assert!(text.contains("BEGIN:VTODO"));
+        assert_eq!(ical::read(&text).unwrap().1, event);
+
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+<multistatus xmlns="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <response>
+    <href>/alice/assignments/uid-2.ics</href>
+    <propstat>
+      <prop><getetag>"abc"</getetag><C:calendar-data>{}</C:calendar-data></prop>
+      <status>HTTP/1.1 200 OK</status>
+    </propstat>
+  </response>
+</multistatus>"#,
+            text
+        );
+        let events = caldav::parse_multistatus(&xml).unwrap();
+        assert_eq!(events[0].id.as_deref(), Some("uid-2.ics"));
+        assert_eq!(events[0].etag.as_deref(), Some("\"abc\""));
+        assert_eq!(events[0].summary, event.summary);
+    }
+
+    /// Needs a CalDAV server, e.g. `radicale --storage-filesystem-folder /tmp/radicale` with
+    /// `CALDAV_TEST_URL=http://localhost:5232/` and a `CALDAV_TEST_COLLECTION` it can write to.
+    /// Run with `cargo test -- --ignored`.
+    #[rocket::async_test]
+    #[ignore]
+    async fn caldav_sync_against_a_server() {
+        let config = CalDavConfig {
+            url: std::env::var("CALDAV_TEST_URL").expect("CALDAV_TEST_URL isn't set"),
+            collection: std::env::var("CALDAV_TEST_COLLECTION").unwrap_or_default(),
+            username: std::env::var("CALDAV_TEST_USERNAME").unwrap_or_default(),
+            password: std::env::var("CALDAV_TEST_PASSWORD").ok(),
+            ..CalDavConfig::default()
+        };
+        let backend = CalDavCalendar::from_config(&config);
+        let due = now() + 24 * 60 * 60;
+        let course = format!("test {}", uuid::Uuid::new_v4());
+        let mut first = Assignment::new(course.clone(), due, "first".into(), "job".into());
+        let mut second = Assignment::new(course.clone(), due, "second".into(), "job".into());
+        second.all_day = true;
+        let ours = |events: Vec<Event>| -> Vec<Event> {
+            events
+                .into_iter()
+                .filter(|e| e.description.as_ref().is_some_and(|d| d.contains(&course)))
+                .collect()
+        };
+
+        push_all(&backend, &mut [&mut first, &mut second]).await;
+        let events = ours(backend.list(now()).await.unwrap());
+        assert_eq!(events.len(), 2);
+        let event = events.iter().find(|e| e.summary == "second").unwrap();
+        assert_eq!(event.start, Event::for_assignment(&second).start);
+
+        first.name = "first (renamed)".into();
+        first.due += 60 * 60;
+        push_all(&backend, &mut [&mut first]).await;
+        let events = ours(backend.list(now()).await.unwrap());
+        let event = events
+            .iter()
+            .find(|e| e.id == Some(first.remote_events[backend.name()].id.clone()))
+            .unwrap();
+        assert_eq!(event.summary, "first (renamed)");
+        assert_eq!(event.start, EventTime::At(first.due));
+
+        first.mark_done();
+        second.mark_done();
+        push_all(&backend, &mut [&mut first, &mut second]).await;
+        assert!(ours(backend.list(now()).await.unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use reqwest::{Method, RequestBuilder, Response, Url};
+
+use super::{check, ical, CalendarBackend, Event};
+use crate::config::{CalDavComponent, CalDavConfig};
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// A calendar collection on a CalDAV server. Each assignment is its own `<uid>.ics` resource in
+/// the collection, and that file name is the event id.
+#[derive(Debug)]
+pub struct CalDavCalendar {
+    /// What assignments store this calendar's event ids under.
+    name: String,
+    config: CalDavConfig,
+    client: reqwest::Client,
+}
+
+impl CalDavCalendar {
+    pub fn from_config(config: &CalDavConfig) -> Self {
+        let mut calendar = Self {
+            name: String::new(),
+            config: config.clone(),
+            client: reqwest::Client::new(),
+        };
+        calendar.name = match calendar.collection_url() {
+            Ok(url) => format!("caldav:{}", url),
+            Err(_) => format!("caldav:{}{}", config.url, config.collection),
+        };
+        calendar
+    }
+
+    /// `url` joined with `collection`, with a trailing slash so event urls can be joined on.
+    fn collection_url(&self) -> Result<Url, std::io::Error> {
+        let mut base = self.config.url.clone();
+        if !base.ends_with('/') {
+            base.push('/');
+        }
+        let mut collection = self.config.collection.clone();
+        if !collection.is_empty() && !collection.ends_with('/') {
+            collection.push('/');
+        }
+        Url::parse(&base)
+            .and_then(|base| base.join(&collection))
+            .map_err(|e| std::io::Error::other(format!("Invalid CalDAV url: {}", e)))
+    }
+
+    fn event_url(&self, id: &str) -> Result<Url, std::io::Error> {
+        self.collection_url()?
+            .join(id)
+            .map_err(|e| std::io::Error::other(format!("Invalid CalDAV event id {}: {}", id, e)))
+    }
+
+    fn password(&self) -> Result<Option<String>, std::io::Error> {
+        let Some(file) = &self.config.password_file else {
+            return Ok(self.config.password.clone());
+        };
+        // not `NotFound`, that would pass for a missing event
+        let password = std::fs::read_to_string(file).map_err(|e| {
+            std::io::Error::other(format!(
+                "Could not read the CalDAV password from {}: {}",
+                file.display(),
+                e
+            ))
+        })?;
+        Ok(Some(password.trim_end().to_string()))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, std::io::Error> {
+        let request = match self.config.username.as_str() {
+            "" => request,
+            username => request.basic_auth(username, self.password()?),
+        };
+        let response = request.send().await.map_err(std::io::Error::other)?;
+        check(response).await
+    }
+
+    /// Writes `event` to the resource `id`. With `create` set it fails instead of overwriting
+    /// something that is already there.
+    async fn put(&self, id: &str, event: &Event, create: bool) -> Result<Event, std::io::Error> {
+        let uid = id.strip_suffix(".ics").unwrap_or(id);
+        let mut request = self
+            .client
+            .put(self.event_url(id)?)
+            .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+            .body(ical::write(uid, self.config.component, event));
+        if create {
+            request = request.header(IF_NONE_MATCH, "*");
+        }
+        let response = self.send(request).await?;
+        // servers may leave it out when they changed what was sent, the next list fills it in
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        Ok(Event {
+            id: Some(id.to_string()),
+            etag,
+            ..event.clone()
+        })
+    }
+}
+
+fn component_name(component: CalDavComponent) -> &'static str {
+    match component {
+        CalDavComponent::Event => "VEVENT",
+        CalDavComponent::Todo => "VTODO",
+    }
+}
+
+/// Text of the first element called `name` under `node`, if it isn't empty.
+fn find<'a>(node: roxmltree::Node<'a, '_>, name: (&str, &str)) -> Option<&'a str> {
+    node.descendants()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+/// The events in a `multistatus` answer to a `calendar-query`. Resources that aren't events or
+/// to-dos we can read are skipped.
+pub(super) fn parse_multistatus(xml: &str) -> Result<Vec<Event>, std::io::Error> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| std::io::Error::other(format!("CalDAV server sent invalid XML: {}", e)))?;
+    let mut events = Vec::new();
+    for response in document
+        .descendants()
+        .filter(|n| n.has_tag_name((DAV, "response")))
+    {
+        let (Some(href), Some(data)) = (
+            find(response, (DAV, "href")),
+            find(response, (CALDAV, "calendar-data")),
+        ) else {
+            continue;
+        };
+        let Some((_, mut event)) = ical::read(data) else {
+            continue;
+        };
+        event.id = href
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .map(str::to_string);
+        event.etag = find(response, (DAV, "getetag")).map(str::to_string);
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[rocket::async_trait]
+impl CalendarBackend for CalDavCalendar {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn list(&self, from: u64) -> Result<Vec<Event>, std::io::Error> {
+        let query = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <D:getetag/>
+    <C:calendar-data/>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="{}">
+        <C:time-range start="{}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#,
+            component_name(self.config.component),
+            ical::format_utc(from)
+        );
+        let request = self
+            .client
+            .request(
+                Method::from_bytes(b"REPORT").unwrap(),
+                self.collection_url()?,
+            )
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(query);
+        let xml = self
+            .send(request)
+            .await?
+            .text()
+            .await
+            .map_err(std::io::Error::other)?;
+        parse_multistatus(&xml)
+    }
+
+    async fn create(&self, event: &Event) -> Result<Event, std::io::Error> {
+        let id = format!("{}.ics", uuid::Uuid::new_v4());
+        self.put(&id, event, true).await
+    }
+
+    async fn update(&self, event: &Event) -> Result<Event, std::io::Error> {
+        let id = event
+            .id
+            .as_deref()
+            .ok_or_else(|| std::io::Error::other("Can't update an event without an id"))?;
+        // a plain PUT makes the event again if it was deleted, which is what push would do anyway
+        self.put(id, event, false).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), std::io::Error> {
+        self.send(self.client.delete(self.event_url(id)?)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use reqwest::header::IF_MATCH;
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::OffsetDateTime;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{check, CalendarBackend, Event, EventTime};
+use crate::config::GoogleConfig;
+use crate::manager::now;
+
+/// What `token_file` holds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// An event as the Calendar API sends it. Only the fields we use.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct GoogleEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub start: GoogleTime,
+    pub end: GoogleTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct GoogleTime {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct EventList {
+    pub items: Vec<GoogleEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+pub(super) fn format_time(time: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(time as i64)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+pub(super) fn parse_time(time: &str) -> Option<u64> {
+    let time = OffsetDateTime::parse(time, &Rfc3339).ok()?;
+    u64::try_from(time.unix_timestamp()).ok()
+}
+
+impl From<&EventTime> for GoogleTime {
+    fn from(time: &EventTime) -> Self {
+        match time {
+            EventTime::At(time) => Self {
+                date_time: Some(format_time(*time)),
+                date: None,
+            },
+            EventTime::Day(day) => Self {
+                date_time: None,
+                date: Some(day.clone()),
+            },
+        }
+    }
+}
+
+impl GoogleTime {
+    fn to_event_time(&self) -> Option<EventTime> {
+        match (&self.date_time, &self.date) {
+            (Some(time), _) => parse_time(time).map(EventTime::At),
+            (None, Some(day)) => Some(EventTime::Day(day.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+impl From<&Event> for GoogleEvent {
+    fn from(event: &Event) -> Self {
+        Self {
+            id: None,
+            etag: None,
+            summary: Some(event.summary.clone()),
+            description: event.description.clone(),
+            start: (&event.start).into(),
+            end: (&event.end).into(),
+        }
+    }
+}
+
+impl GoogleEvent {
+    /// `None` for events with times we can't read.
+    fn to_event(&self) -> Option<Event> {
+        Some(Event {
+            id: self.id.clone(),
+            etag: self.etag.clone(),
+            summary: self.summary.clone().unwrap_or_default(),
+            description: self.description.clone(),
+            start: self.start.to_event_time()?,
+            end: self.end.to_event_time()?,
+        })
+    }
+}
+
+/// Google Calendar through its v3 REST API. Access tokens come from the refresh token in
+/// `token_file` and are kept until they expire.
+#[derive(Debug)]
+pub struct GoogleCalendar {
+    /// What assignments store this calendar's event ids under.
+    name: String,
+    config: GoogleConfig,
+    client: reqwest::Client,
+    /// Used instead of reading `token_file` when set.
+    credentials: Option<Credentials>,
+    /// Access token and when it expires.
+    token: Mutex<Option<(String, u64)>>,
+}
+
+impl GoogleCalendar {
+    pub fn from_config(config: &GoogleConfig) -> Self {
+        Self {
+            name: format!("google:{}", config.calendar_id),
+            config: config.clone(),
+            client: reqwest::Client::new(),
+            credentials: None,
+            token: Mutex::new(None),
+        }
+    }
+
+    pub fn with_credentials(name: String, config: &GoogleConfig, credentials: Credentials) -> Self {
+        Self {
+            name,
+            credentials: Some(credentials),
+            ..Self::from_config(config)
+        }
+    }
+
+    fn credentials(&self) -> Result<Credentials, std::io::Error> {
+        if let Some(credentials) = &self.credentials {
+            return Ok(credentials.clone());
+        }
+        // not `NotFound`, that would pass for a missing event
+        let file = std::fs::read_to_string(&self.config.token_file).map_err(|e| {
+            std::io::Error::other(format!(
+                "Could not read google credentials from {}: {}",
+                self.config.token_file.display(),
+                e
+            ))
+        })?;
+        serde_json::from_str(&file).map_err(std::io::Error::other)
+    }
+
+    async fn access_token(&self) -> Result<String, std::io::Error> {
+        let mut token = self.token.lock().await;
+        if let Some((token, expires)) = &*token {
+            // leave some room for the request to get there
+            if *expires > now() + 60 {
+                return Ok(token.clone());
+            }
+        }
+        let credentials = self.credentials()?;
+        let response = self
+            .client
+            .post(&self.config.token_url)
+            .form(&[
+                ("client_id", credentials.client_id.as_str()),
+                ("client_secret", credentials.client_secret.as_str()),
+                ("refresh_token", credentials.refresh_token.as_str()),
+                ("grant_type", "refresh_token"),
+            ])
+            .send()
+            .await
+            .map_err(std::io::Error::other)?;
+        let response: TokenResponse = check(response)
+            .await?
+            .json()
+            .await
+            .map_err(std::io::Error::other)?;
+        *token = Some((response.access_token.clone(), now() + response.expires_in));
+        Ok(response.access_token)
+    }
+
+    /// `.../calendars/<calendar_id>/events`, followed by `id` if there is one.
+    fn events_url(&self, id: Option<&str>) -> Result<Url, std::io::Error> {
+        let mut url = Url::parse(&self.config.api_base).map_err(std::io::Error::other)?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| std::io::Error::other("api_base can't be a base url"))?;
+            segments
+                .pop_if_empty()
+                .push("calendars")
+                .push(&self.config.calendar_id)
+                .push("events");
+            if let Some(id) = id {
+                segments.push(id);
+            }
+        }
+        Ok(url)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, std::io::Error> {
+        check(self.send_unchecked(request).await?).await
+    }
+
+    async fn send_unchecked(&self, request: RequestBuilder) -> Result<Response, std::io::Error> {
+        request
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .map_err(std::io::Error::other)
+    }
+
+    /// Replaces the event `id` with `event`, only if it is still at `etag` when there is one.
+    async fn put(
+        &self,
+        id: &str,
+        event: &Event,
+        etag: Option<&str>,
+    ) -> Result<Response, std::io::Error> {
+        let mut request = self
+            .client
+            .put(self.events_url(Some(id))?)
+            .json(&GoogleEvent::from(event));
+        if let Some(etag) = etag {
+            request = request.header(IF_MATCH, etag);
+        }
+        self.send_unchecked(request).await
+    }
+}
+
+fn to_event(event: GoogleEvent) -> Result<Event, std::io::Error> {
+    event
+        .to_event()
+        .ok_or_else(|| std::io::Error::other("Calendar API returned an event without a time"))
+}
+
+#[rocket::async_trait]
+impl CalendarBackend for GoogleCalendar {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn list(&self, from: u64) -> Result<Vec<Event>, std::io::Error> {
+        let mut events = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.client.get(self.events_url(None)?).query(&[
+                ("timeMin", format_time(from).as_str()),
+                ("singleEvents", "true"),
+                ("maxResults", "2500"),
+            ]);
+            if let Some(page_token) = &page_token {
+                request = request.query(&[("pageToken", page_token)]);
+            }
+            let page: EventList = self
+                .send(request)
+                .await?
+                .json()
+                .await
+                .map_err(std::io::Error::other)?;
+            events.extend(page.items.iter().filter_map(GoogleEvent::to_event));
+            match page.next_page_token {
+                Some(next) => page_token = Some(next),
+                None => return Ok(events),
+            }
+        }
+    }
+
+    async fn create(&self, event: &Event) -> Result<Event, std::io::Error> {
+        let request = self
+            .client
+            .post(self.events_url(None)?)
+            .json(&GoogleEvent::from(event));
+        let created: GoogleEvent = self
+            .send(request)
+            .await?
+            .json()
+            .await
+            .map_err(std::io::Error::other)?;
+        to_event(created)
+    }
+
+    async fn update(&self, event: &Event) -> Result<Event, std::io::Error> {
+        let id = event
+            .id
+            .as_deref()
+            .ok_or_else(|| std::io::Error::other("Can't update an event without an id"))?;
+        let mut response = self.put(id, event, event.etag.as_deref()).await?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            // changed on the calendar since we last saw it. Assignments are ours to say, so it
+            // gets overwritten, unless someone already made it look the way we want.
+            let current = self
+                .list(0)
+                .await?
+                .into_iter()
+                .find(|current| current.id.as_deref() == Some(id));
+            match current {
+                Some(current) if !current.differs_from(event) => return Ok(current),
+                Some(current) => response = self.put(id, event, current.etag.as_deref()).await?,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "Event was deleted from the calendar",
+                    ))
+                }
+            }
+        }
+        let updated: GoogleEvent = check(response)
+            .await?
+            .json()
+            .await
+            .map_err(std::io::Error::other)?;
+        to_event(updated)
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), std::io::Error> {
+        self.send(self.client.delete(self.events_url(Some(id))?))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! Just enough iCalendar (RFC 5545) to write assignments out and read them back in.
+
+use rocket::time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+use super::{Event, EventTime};
+use crate::config::CalDavComponent;
+use crate::manager::now;
+
+/// `20261019T042618Z`
+pub(super) fn format_utc(time: u64) -> String {
+    let time =
+        OffsetDateTime::from_unix_timestamp(time as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn parse_date(date: &str) -> Option<Date> {
+    if date.len() != 8 || !date.is_ascii() {
+        return None;
+    }
+    let month = Month::try_from(date[4..6].parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(date[..4].parse().ok()?, month, date[6..].parse().ok()?).ok()
+}
+
+/// Only UTC times, which is all we write. Times in other zones are skipped.
+fn parse_utc(time: &str) -> Option<u64> {
+    let (date, time) = time.strip_suffix('Z')?.split_once('T')?;
+    if time.len() != 6 || !time.is_ascii() {
+        return None;
+    }
+    let time = Time::from_hms(
+        time[..2].parse().ok()?,
+        time[2..4].parse().ok()?,
+        time[4..].parse().ok()?,
+    )
+    .ok()?;
+    let time = PrimitiveDateTime::new(parse_date(date)?, time).assume_utc();
+    u64::try_from(time.unix_timestamp()).ok()
+}
+
+/// `YYYY-MM-DD` to `YYYYMMDD`.
+fn format_day(day: &str) -> String {
+    day.replace('-', "")
+}
+
+/// `YYYY-MM-DD`
+fn date_string(date: Date) -> String {
+    format!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        date.month() as u8,
+        date.day()
+    )
+}
+
+fn parse_day(day: &str) -> Option<String> {
+    parse_date(day).map(date_string)
+}
+
+fn next_day(day: &str) -> Option<String> {
+    parse_date(&format_day(day))?.next_day().map(date_string)
+}
+
+/// Escapes text values.
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Lines can only be 75 bytes long, longer ones carry on in lines starting with a space.
+pub fn push_line(out: &mut String, line: &str) {
+    let mut rest = line;
+    let mut limit = 75;
+    while rest.len() > limit {
+        let mut split = limit;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        out.push_str(&rest[..split]);
+        out.push_str("\r\n ");
+        rest = &rest[split..];
+        // the space counts towards the next line
+        limit = 74;
+    }
+    out.push_str(rest);
+    out.push_str("\r\n");
+}
+
+fn push_time(out: &mut String, name: &str, time: &EventTime) {
+    match time {
+        EventTime::At(time) => push_line(out, &format!("{}:{}", name, format_utc(*time))),
+        EventTime::Day(day) => push_line(out, &format!("{};VALUE=DATE:{}", name, format_day(day))),
+    }
+}
+
+/// The component for `event` with the given UID, without the surrounding `VCALENDAR`.
+pub fn write_component(out: &mut String, uid: &str, component: CalDavComponent, event: &Event) {
+    let name = match component {
+        CalDavComponent::Event => "VEVENT",
+        CalDavComponent::Todo => "VTODO",
+    };
+    push_line(out, &format!("BEGIN:{}", name));
+    push_line(out, &format!("UID:{}", uid));
+    push_line(out, &format!("DTSTAMP:{}", format_utc(now())));
+    push_line(out, &format!("SUMMARY:{}", escape(&event.summary)));
+    if let Some(description) = &event.description {
+        push_line(out, &format!("DESCRIPTION:{}", escape(description)));
+    }
+    match component {
+        CalDavComponent::Event => {
+            push_time(out, "DTSTART", &event.start);
+            push_time(out, "DTEND", &event.end);
+        }
+        // to-dos only have a due date, which is when the assignment starts
+        CalDavComponent::Todo => push_time(out, "DUE", &event.start),
+    }
+    push_line(out, &format!("END:{}", name));
+}
+
+pub fn begin_calendar(out: &mut String) {
+    push_line(out, "BEGIN:VCALENDAR");
+    push_line(out, "VERSION:2.0");
+    push_line(out, "PRODID:-//calendarwebthing//EN");
+}
+
+pub fn end_calendar(out: &mut String) {
+    push_line(out, "END:VCALENDAR");
+}
+
+/// A whole calendar object holding just `event`.
+pub fn write(uid: &str, component: CalDavComponent, event: &Event) -> String {
+    let mut out = String::new();
+    begin_calendar(&mut out);
+    write_component(&mut out, uid, component, event);
+    end_calendar(&mut out);
+    out
+}
+
+/// Joins folded lines back together.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn read_time(params: &str, value: &str) -> Option<EventTime> {
+    if params.contains("VALUE=DATE") && !params.contains("VALUE=DATE-TIME") {
+        parse_day(value).map(EventTime::Day)
+    } else {
+        parse_utc(value).map(EventTime::At)
+    }
+}
+
+/// The UID and event of the first `VEVENT` or `VTODO` in `text`. `None` when there isn't one or
+/// it has times we can't read. The event's id is left unset.
+pub fn read(text: &str) -> Option<(String, Event)> {
+    // 1 inside the component, more inside something nested in it like a VALARM
+    let mut depth = 0;
+    let mut uid = None;
+    let mut summary = String::new();
+    let mut description = None;
+    let mut start = None;
+    let mut end = None;
+    let mut due = None;
+    for line in unfold(text) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        match (name.to_ascii_uppercase().as_str(), depth) {
+            ("BEGIN", 0) if value == "VEVENT" || value == "VTODO" => depth = 1,
+            ("BEGIN", 1..) => depth += 1,
+            ("END", 1) => break,
+            ("END", 2..) => depth -= 1,
+            ("UID", 1) => uid = Some(value.to_string()),
+            ("SUMMARY", 1) => summary = unescape(value),
+            ("DESCRIPTION", 1) => description = Some(unescape(value)),
+            ("DTSTART", 1) => start = Some(read_time(params, value)?),
+            ("DTEND", 1) => end = Some(read_time(params, value)?),
+            ("DUE", 1) => due = Some(read_time(params, value)?),
+            _ => {}
+        }
+    }
+    let start = start.or(due)?;
+    let end = match (end, &start) {
+        (Some(end), _) => end,
+        (None, EventTime::Day(day)) => EventTime::Day(next_day(day)?),
+        (None, EventTime::At(time)) => EventTime::At(*time),
+    };
+    Some((
+        uid?,
+        Event {
+            id: None,
+            etag: None,
+            summary,
+            description,
+            start,
+            end,
+        },
+    ))
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+
+use super::google::{parse_time, Credentials, EventList, GoogleEvent};
+use super::GoogleCalendar;
+use crate::config::GoogleConfig;
+use crate::network::Headers;
+
+/// Where `get_routes` is mounted when the `mock` calendar is configured.
+pub const MOUNT: &str = "/mock/google";
+
+const TOKEN: &str = "mock-token";
+
+/// Events by calendar id. Kept in memory only.
+static CALENDARS: LazyLock<Mutex<HashMap<String, Vec<GoogleEvent>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static ETAG: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+fn next_etag() -> String {
+    let etag = ETAG.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+    format!("\"{}\"", etag)
+}
+
+/// Points a `GoogleCalendar` at the mock mounted on this server.
+pub fn config() -> GoogleConfig {
+    base_config(&format!("http://127.0.0.1:{}{}", crate::PORT, MOUNT))
+}
+
+/// `GoogleConfig` for a mock at `base`, which is everything up to and including `MOUNT`.
+pub fn base_config(base: &str) -> GoogleConfig {
+    GoogleConfig {
+        api_base: format!("{}/calendar/v3", base),
+        token_url: format!("{}/token", base),
+        ..GoogleConfig::default()
+    }
+}
+
+/// A `GoogleCalendar` talking to the mock described by `config`.
+pub fn backend(config: &GoogleConfig) -> GoogleCalendar {
+    GoogleCalendar::with_credentials(
+        format!("mock:{}", config.calendar_id),
+        config,
+        credentials(),
+    )
+}
+
+/// What the mock's token endpoint accepts.
+fn credentials() -> Credentials {
+    Credentials {
+        client_id: "mock".to_string(),
+        client_secret: "mock".to_string(),
+        refresh_token: "mock".to_string(),
+    }
+}
+
+fn authorized(headers: &Headers) -> Result<(), Status> {
+    match headers.get("authorization") {
+        Some(value) if value == format!("Bearer {}", TOKEN) => Ok(()),
+        _ => Err(Status::Unauthorized),
+    }
+}
+
+#[post("/token")]
+fn token() -> rocket::serde::json::Value {
+    rocket::serde::json::json!({
+        "access_token": TOKEN,
+        "expires_in": 3600,
+        "token_type": "Bearer",
+    })
+}
+
+#[derive(FromForm)]
+struct ListQuery {
+    #[field(name = "timeMin")]
+    time_min: Option<String>,
+    #[field(name = "pageToken")]
+    page_token: Option<usize>,
+    #[field(name = "maxResults")]
+    max_results: Option<usize>,
+}
+
+#[get("/calendar/v3/calendars/<calendar>/events?<query..>")]
+fn list_events(
+    calendar: &str,
+    query: ListQuery,
+    headers: Headers,
+) -> Result<Json<EventList>, Status> {
+    authorized(&headers)?;
+    let from = query.time_min.as_deref().and_then(parse_time).unwrap_or(0);
+    let calendars = CALENDARS.lock().unwrap();
+    let events: Vec<&GoogleEvent> = calendars
+        .get(calendar)
+        .map(|events| {
+            events
+                .iter()
+                // all day events are always listed, close enough for a mock
+                .filter(|event| {
+                    event
+                        .end
+                        .date_time
+                        .as_deref()
+                        .and_then(parse_time)
+                        .is_none_or(|end| end >= from)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let start = query.page_token.unwrap_or(0);
+    // Google pages at 2500, smaller pages here make sure paging gets used
+    let end = start + query.max_results.unwrap_or(250).min(250);
+    Ok(Json(EventList {
+        items: events
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .cloned()
+            .cloned()
+            .collect(),
+        next_page_token: (end < events.len()).then(|| end.to_string()),
+    }))
+}
+
+#[post("/calendar/v3/calendars/<calendar>/events", data = "<event>")]
+fn create_event(
+    calendar: &str,
+    event: Json<GoogleEvent>,
+    headers: Headers,
+) -> Result<Json<GoogleEvent>, Status> {
+    authorized(&headers)?;
+    let mut event = event.into_inner();
+    event.id = Some(uuid::Uuid::new_v4().simple().to_string());
+    event.etag = Some(next_etag());
+    CALENDARS
+        .lock()
+        .unwrap()
+        .entry(calendar.to_string())
+        .or_default()
+        .push(event.clone());
+    Ok(Json(event))
+}
+
+#[put("/calendar/v3/calendars/<calendar>/events/<id>", data = "<event>")]
+fn update_event(
+    calendar: &str,
+    id: &str,
+    event: Json<GoogleEvent>,
+    headers: Headers,
+) -> Result<Json<GoogleEvent>, Status> {
+    authorized(&headers)?;
+    let mut calendars = CALENDARS.lock().unwrap();
+    let existing = calendars
+        .get_mut(calendar)
+        .and_then(|events| events.iter_mut().find(|e| e.id.as_deref() == Some(id)))
+        .ok_or(Status::NotFound)?;
+    if headers
+        .get("if-match")
+        .is_some_and(|etag| Some(etag) != existing.etag.as_deref())
+    {
+        return Err(Status::PreconditionFailed);
+    }
+    let mut event = event.into_inner();
+    event.id = Some(id.to_string());
+    event.etag = Some(next_etag());
+    *existing = event.clone();
+    Ok(Json(event))
+}
+
+#[delete("/calendar/v3/calendars/<calendar>/events/<id>")]
+fn delete_event(calendar: &str, id: &str, headers: Headers) -> Result<Status, Status> {
+    authorized(&headers)?;
+    let mut calendars = CALENDARS.lock().unwrap();
+    let events = calendars.get_mut(calendar).ok_or(Status::NotFound)?;
+    let len = events.len();
+    events.retain(|e| e.id.as_deref() != Some(id));
+    if events.len() == len {
+        return Err(Status::Gone);
+    }
+    Ok(Status::NoContent)
+}
+
+/// A stand-in for the parts of Google's token endpoint and Calendar v3 API that
+/// `GoogleCalendar` uses, to be mounted at `MOUNT`.
+pub fn get_routes() -> Vec<rocket::Route> {
+    routes![token, list_events, create_event, update_event, delete_event]
+}
@@ -0,0 +1,166 @@
+use std::{path::PathBuf, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+/// Where assignments are pushed to. `mock` serves a fake Google Calendar from this server, for
+/// trying things out without an account.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarKind {
+    #[default]
+    Google,
+    Mock,
+    CalDav,
+}
+
+/// How to reach Google Calendar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GoogleConfig {
+    pub calendar_id: String,
+    /// `token.json` as written by `google_sync/google.js` the first time it is authorized. Holds
+    /// the client id, client secret and refresh token.
+    pub token_file: PathBuf,
+    pub api_base: String,
+    pub token_url: String,
+}
+
+impl Default for GoogleConfig {
+    fn default() -> Self {
+        Self {
+            calendar_id: "primary".to_string(),
+            token_file: Config::get_data_dir().join("google_token.json"),
+            api_base: "https://www.googleapis.com/calendar/v3".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+        }
+    }
+}
+
+/// What assignments become on a CalDAV calendar.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, FromFormField, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CalDavComponent {
+    #[default]
+    Event,
+    Todo,
+}
+
+/// How to reach a CalDAV calendar (Nextcloud, Radicale, Fastmail, ...). Used for every job when
+/// `calendar` is `caldav`, and by jobs that set their own.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, ToSchema)]
+#[serde(default)]
+pub struct CalDavConfig {
+    /// The server, e.g. `https://cloud.example.com/remote.php/dav/`.
+    pub url: String,
+    /// The calendar collection, relative to `url`, e.g. `calendars/alice/personal/`.
+    pub collection: String,
+    /// Left empty for servers that don't need a login.
+    pub username: String,
+    /// Stored as is in the config or save file. Prefer `password_file`. It is never sent back by
+    /// the API or shown on pages.
+    pub password: Option<String>,
+    /// Read the password from this file instead, so it doesn't have to be stored in the config.
+    /// This is the recommended way to give the password.
+    #[schema(value_type = Option<String>)]
+    pub password_file: Option<PathBuf>,
+    pub component: CalDavComponent,
+}
+
+/// Leaves the password out, the manager data gets logged.
+impl std::fmt::Debug for CalDavConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CalDavConfig")
+            .field("url", &self.url)
+            .field("collection", &self.collection)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("password_file", &self.password_file)
+            .field("component", &self.component)
+            .finish()
+    }
+}
+
+/// Settings read from `~/.calendarthing/config.json`. Every field has a default so a missing or
+/// partial file is fine.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub storage: StorageKind,
+    /// How many timestamped copies of `manager.json` to keep in `backups/`.
+    pub backup_count: usize,
+    /// Minimum number of seconds between two backups.
+    pub backup_interval: u64,
+    /// Days to keep past due assignments in the archive, counted from their due date. 0 keeps them
+    /// forever.
+    pub archive_retention: u64,
+    /// Runs to keep in each job's history.
+    pub run_history: usize,
+    /// How many jobs may run at the same time.
+    pub max_concurrent_jobs: usize,
+    pub calendar: CalendarKind,
+    pub google: GoogleConfig,
+    pub caldav: CalDavConfig,
+    /// Seconds before retrying a calendar push that failed. Doubles with every failure.
+    pub sync_backoff_base: u64,
+    /// Longest wait between calendar push retries, in seconds.
+    pub sync_backoff_cap: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storage: StorageKind::default(),
+            backup_count: 10,
+            backup_interval: 60 * 60,
+            archive_retention: 365,
+            run_history: 20,
+            max_concurrent_jobs: 4,
+            calendar: CalendarKind::default(),
+            google: GoogleConfig::default(),
+            caldav: CalDavConfig::default(),
+            sync_backoff_base: 30,
+            sync_backoff_cap: 60 * 60,
+        }
+    }
+}
+
+impl Config {
+    pub fn get_data_dir() -> PathBuf {
+        let home_dir = home::home_dir().unwrap();
+        home_dir.join(".calendarthing")
+    }
+    fn get_config_file() -> PathBuf {
+        Self::get_data_dir().join("config.json")
+    }
+
+    /// The config for this process, read once on first use.
+    pub fn get() -> &'static Config {
+        static CONFIG: OnceLock<Config> = OnceLock::new();
+        CONFIG.get_or_init(Self::load)
+    }
+
+    pub fn load() -> Self {
+        let file = match std::fs::read_to_string(Self::get_config_file()) {
+            Ok(v) => v,
+            Err(_) => return Self::default(),
+        };
+        match serde_json::from_str(&file) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Invalid config file, using defaults: {}", e);
+                Self::default()
+            }
+        }
+    }
+}
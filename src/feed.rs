@@ -0,0 +1,233 @@
+use rocket::http::ContentType;
+
+use crate::calendar::{ical, Event};
+use crate::config::CalDavComponent;
+use crate::manager::{self, Assignment, ManagerData};
+
+/// Calendar apps tell events apart by UID across refreshes, so it comes from the assignment's id,
+/// which stays the same when a job scrapes the assignment again.
+fn uid(assignment: &Assignment) -> String {
+    format!("{}@calendarwebthing", assignment.id)
+}
+
+/// A whole calendar called `name` with `assignments` as events. `done` works like it does for the
+/// API's assignment list, so `?done=false` leaves out the ones that are done.
+fn render<'a>(
+    name: &str,
+    assignments: impl Iterator<Item = &'a Assignment>,
+    done: Option<bool>,
+) -> (ContentType, String) {
+    let mut out = String::new();
+    ical::begin_calendar(&mut out);
+    ical::push_line(&mut out, &format!("X-WR-CALNAME:{}", ical::escape(name)));
+    // how often subscribers should check for changes, when they listen
+    ical::push_line(&mut out, "REFRESH-INTERVAL;VALUE=DURATION:PT1H");
+    ical::push_line(&mut out, "X-PUBLISHED-TTL:PT1H");
+    for assignment in assignments.filter(|a| done.is_none_or(|done| a.done == done)) {
+        ical::write_component(
+            &mut out,
+            &uid(assignment),
+            CalDavComponent::Event,
+            &Event::for_assignment(assignment),
+        );
+    }
+    ical::end_calendar(&mut out);
+    (ContentType::Calendar, out)
+}
+
+fn all_feed(data: &ManagerData, done: Option<bool>) -> (ContentType, String) {
+    render("Assignments", data.assignments.iter(), done)
+}
+
+fn course_feed(
+    data: &ManagerData,
+    course: &str,
+    done: Option<bool>,
+) -> Option<(ContentType, String)> {
+    let assignments: Vec<&Assignment> = data
+        .assignments
+        .iter()
+        .filter(|a| a.course == course)
+        .collect();
+    if assignments.is_empty() {
+        return None;
+    }
+    Some(render(
+        &format!("{} assignments", course),
+        assignments.into_iter(),
+        done,
+    ))
+}
+
+fn job_feed(data: &ManagerData, id: &str, done: Option<bool>) -> Option<(ContentType, String)> {
+    let job = data.get_job_by_id(id)?;
+    let assignments = data
+        .assignments
+        .iter()
+        .filter(|a| a.job_name.as_ref() == Some(&job.name));
+    Some(render(
+        &format!("{} assignments", job.name),
+        assignments,
+        done,
+    ))
+}
+
+/// Every assignment.
+#[get("/all.ics?<done>")]
+fn all_assignments(done: Option<bool>) -> (ContentType, String) {
+    all_feed(&manager::Manager::read_no_save(), done)
+}
+
+/// One course's assignments. Courses only exist through their assignments, so one nothing has
+/// been due for is not found.
+#[get("/courses/<course>/assignments.ics?<done>")]
+fn course_assignments(course: &str, done: Option<bool>) -> Option<(ContentType, String)> {
+    course_feed(&manager::Manager::read_no_save(), course, done)
+}
+
+/// The assignments a job scraped.
+#[get("/jobs/<id>/assignments.ics?<done>")]
+fn job_assignments(id: &str, done: Option<bool>) -> Option<(ContentType, String)> {
+    job_feed(&manager::Manager::read_no_save(), id, done)
+}
+
+/// Read-only iCalendar feeds any calendar app can subscribe to, instead of pushing to one.
+pub fn get_routes() -> Vec<rocket::Route> {
+    routes![all_assignments, course_assignments, job_assignments]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::{now, Job};
+
+    fn assignment(course: &str, name: &str, job: &str, done: bool) -> Assignment {
+        let mut assignment = Assignment::new(
+            course.to_string(),
+            now() + 24 * 60 * 60,
+            name.to_string(),
+            job.to_string(),
+        );
+        assignment.done = done;
+        assignment
+    }
+
+    fn data() -> ManagerData {
+        let mut data = ManagerData::empty();
+        data.jobs
+            .push(Job::new("canvas".into(), "/tmp".into(), false, 60));
+        data.jobs
+            .push(Job::new("idle".into(), "/tmp".into(), false, 60));
+        data.assignments = vec![
+            assignment("math", "Problem set", "canvas", false),
+            assignment("math", "Quiz", "canvas", true),
+            assignment("history", "Essay", "manual", false),
+        ];
+        data
+    }
+
+    /// The feed with folded lines joined back up.
+    fn unfold(feed: &str) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for line in feed.split("\r\n").filter(|line| !line.is_empty()) {
+            match (line.strip_prefix(' '), lines.last_mut()) {
+                (Some(rest), Some(last)) => last.push_str(rest),
+                _ => lines.push(line.to_string()),
+            }
+        }
+        lines
+    }
+
+    /// The value of every `name` line, in order.
+    fn values(feed: &str, name: &str) -> Vec<String> {
+        let prefix = format!("{}:", name);
+        unfold(feed)
+            .into_iter()
+            .filter_map(|line| line.strip_prefix(&prefix).map(str::to_string))
+            .collect()
+    }
+
+    fn summaries(feed: &(ContentType, String)) -> Vec<String> {
+        assert_eq!(feed.0, ContentType::Calendar);
+        assert!(feed.1.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(feed.1.ends_with("END:VCALENDAR\r\n"));
+        values(&feed.1, "SUMMARY")
+    }
+
+    #[test]
+    fn all_feed_filters_on_done() {
+        let data = data();
+        assert_eq!(
+            summaries(&all_feed(&data, None)),
+            ["Problem set", "Quiz", "Essay"]
+        );
+        assert_eq!(
+            summaries(&all_feed(&data, Some(false))),
+            ["Problem set", "Essay"]
+        );
+        assert_eq!(summaries(&all_feed(&data, Some(true))), ["Quiz"]);
+        assert_eq!(
+            values(&all_feed(&data, None).1, "X-WR-CALNAME"),
+            ["Assignments"]
+        );
+    }
+
+    #[test]
+    fn course_feed_has_only_that_course() {
+        let data = data();
+        let feed = course_feed(&data, "math", None).unwrap();
+        assert_eq!(summaries(&feed), ["Problem set", "Quiz"]);
+        assert_eq!(values(&feed.1, "X-WR-CALNAME"), ["math assignments"]);
+        let feed = course_feed(&data, "math", Some(false)).unwrap();
+        assert_eq!(summaries(&feed), ["Problem set"]);
+        assert!(course_feed(&data, "art", None).is_none());
+    }
+
+    #[test]
+    fn job_feed_has_only_that_job() {
+        let data = data();
+        let canvas = data.jobs[0].id.clone();
+        let feed = job_feed(&data, &canvas, None).unwrap();
+        assert_eq!(summaries(&feed), ["Problem set", "Quiz"]);
+        assert_eq!(values(&feed.1, "X-WR-CALNAME"), ["canvas assignments"]);
+        let feed = job_feed(&data, &canvas, Some(true)).unwrap();
+        assert_eq!(summaries(&feed), ["Quiz"]);
+        // a job that found nothing still has a feed, just an empty one
+        let idle = data.jobs[1].id.clone();
+        assert!(summaries(&job_feed(&data, &idle, None).unwrap()).is_empty());
+        assert!(job_feed(&data, "missing", None).is_none());
+    }
+
+    #[test]
+    fn names_are_escaped_and_folded() {
+        let mut data = data();
+        let name = "Lab 3; part 2, with a \\ and a name long enough to need folding, ünïcödé ✓✓✓✓";
+        data.assignments = vec![assignment("bio, 101", name, "canvas", false)];
+        let feed = course_feed(&data, "bio, 101", None).unwrap();
+
+        for line in feed.1.split("\r\n") {
+            assert!(line.len() <= 75, "{:?} is too long", line);
+        }
+        assert!(feed.1.contains("\r\n "), "nothing was folded");
+        assert_eq!(values(&feed.1, "SUMMARY"), [ical::escape(name)]);
+        assert!(values(&feed.1, "SUMMARY")[0].starts_with("Lab 3\\; part 2\\, with a \\\\ and"));
+        assert_eq!(values(&feed.1, "X-WR-CALNAME"), ["bio\\, 101 assignments"]);
+    }
+
+    #[test]
+    fn uids_survive_a_rescrape() {
+        let mut data = data();
+        let id = data.assignments[0].id.clone();
+        let before = values(&all_feed(&data, None).1, "UID");
+        assert_eq!(before[0], format!("{}@calendarwebthing", id));
+
+        // scraped again with a new due date, coming in with a fresh id like every scrape does
+        let mut rescraped = assignment("math", "Problem set", "canvas", false);
+        rescraped.due += 60 * 60;
+        assert_ne!(rescraped.id, id);
+        data.add_assignment(rescraped);
+
+        let after = values(&all_feed(&data, None).1, "UID");
+        assert_eq!(after, before);
+    }
+}
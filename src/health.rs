@@ -0,0 +1,120 @@
+use std::sync::Mutex;
+
+use rocket_dyn_templates::context;
+use rocket_dyn_templates::Template;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::manager::{self, now, ManagerData, RunStatus};
+use crate::network::*;
+
+/// The scheduler is considered stuck when it hasn't finished a pass for this many seconds.
+const STALE_AFTER: u64 = 5 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SchedulerError {
+    pub at: u64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct FailingJob {
+    pub id: String,
+    pub name: String,
+    pub status: RunStatus,
+    /// From the job's last run.
+    pub error: Option<String>,
+    pub at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct Health {
+    /// False when the scheduler is failing or stuck, or a job's last run failed.
+    pub ok: bool,
+    /// When the scheduler last finished a pass without errors.
+    pub last_tick: Option<u64>,
+    /// Set when the last pass failed. Cleared by the next one that works.
+    pub scheduler_error: Option<SchedulerError>,
+    pub failing_jobs: Vec<FailingJob>,
+}
+
+/// What the scheduler thread reports about itself. The job side of `Health` comes from the save
+/// data, so it survives restarts.
+static SCHEDULER: Mutex<(Option<u64>, Option<SchedulerError>)> = Mutex::new((None, None));
+
+pub fn scheduler_ok() {
+    *SCHEDULER.lock().unwrap() = (Some(now()), None);
+}
+
+pub fn scheduler_failed(message: String) {
+    println!("Scheduler pass failed: {}", message);
+    SCHEDULER.lock().unwrap().1 = Some(SchedulerError { at: now(), message });
+}
+
+/// Gets the message out of a caught panic.
+pub fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        format!("Panicked: {}", message)
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        format!("Panicked: {}", message)
+    } else {
+        "Panicked".to_string()
+    }
+}
+
+pub fn get(data: &ManagerData) -> Health {
+    let (last_tick, scheduler_error) = SCHEDULER.lock().unwrap().clone();
+    let failing_jobs: Vec<FailingJob> = data
+        .jobs
+        .iter()
+        .filter(|job| {
+            matches!(
+                job.last_status,
+                Some(RunStatus::Failed) | Some(RunStatus::TimedOut)
+            )
+        })
+        .map(|job| {
+            let run = data.get_runs(&job.id).into_iter().next();
+            FailingJob {
+                id: job.id.clone(),
+                name: job.name.clone(),
+                status: job.last_status.unwrap(),
+                error: run.as_ref().and_then(|run| run.error.clone()),
+                at: run.map(|run| run.finished_at),
+            }
+        })
+        .collect();
+    let stale = last_tick.is_none_or(|tick| tick + STALE_AFTER < now());
+    Health {
+        ok: !stale && scheduler_error.is_none() && failing_jobs.is_empty(),
+        last_tick,
+        scheduler_error,
+        failing_jobs,
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HealthPageData {
+    page: String,
+    health: Health,
+    htmx_request: bool,
+}
+
+/// Banner the layout loads on every page. Empty when everything is fine.
+#[get("/")]
+fn get_health(headers: Headers) -> Template {
+    let page_data = HealthPageData {
+        page: "health".to_string(),
+        health: get(&manager::Manager::read_no_save()),
+        htmx_request: headers.contains("hx-request".to_string()),
+    };
+    if page_data.htmx_request {
+        Template::render("health", context! {page_data})
+    } else {
+        Template::render("layout", context! {page_data})
+    }
+}
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    routes![get_health]
+}
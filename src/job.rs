@@ -4,119 +4,387 @@ use rocket_dyn_templates::Template;
 
 use crate::network::*;
 
+use crate::config::{CalDavComponent, CalDavConfig};
 use crate::manager;
+use crate::schedule::Schedule;
 #[derive(serde::Serialize, serde::Deserialize)]
 struct JobPageData {
     page: String,
     manager: manager::ManagerData,
     job: manager::Job,
+    /// Newest first.
+    runs: Vec<manager::JobRun>,
+    running: bool,
+    /// Every weekday, for the schedule checkboxes.
+    weekdays: Vec<WeekdayOption>,
     htmx_request: bool,
 }
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WeekdayOption {
+    value: u8,
+    name: String,
+    checked: bool,
+}
+impl WeekdayOption {
+    fn all(schedule: &Schedule) -> Vec<Self> {
+        ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"]
+            .iter()
+            .zip(0..)
+            .map(|(name, value)| Self {
+                value,
+                name: name.to_string(),
+                checked: schedule.weekdays.contains(&value),
+            })
+            .collect()
+    }
+}
+/// Command fields shared by the new and edit forms, sent as `command.program` and so on.
+#[derive(FromForm)]
+struct CommandForm {
+    program: Option<String>,
+    args: Option<String>,
+    /// Left empty to keep running in the current working directory, or in the path for new jobs.
+    working_dir: Option<String>,
+    env: Option<String>,
+    stdin: Option<String>,
+}
+impl CommandForm {
+    fn to_command(&self) -> manager::JobCommand {
+        manager::JobCommand::from_form(
+            self.program.as_deref().unwrap_or_default(),
+            self.args.as_deref().unwrap_or_default(),
+            self.working_dir.as_deref().unwrap_or_default(),
+            self.env.as_deref().unwrap_or_default(),
+            self.stdin.as_deref().unwrap_or_default(),
+        )
+    }
+}
+/// A job's own CalDAV calendar, sent as `caldav.url` and so on. Leaving the url empty uses the
+/// configured calendar.
+#[derive(FromForm)]
+struct CalDavForm {
+    url: Option<String>,
+    collection: Option<String>,
+    username: Option<String>,
+    /// Left empty to keep the current password, so it doesn't have to be shown in the form.
+    password: Option<String>,
+    password_file: Option<String>,
+    component: Option<CalDavComponent>,
+}
+impl CalDavForm {
+    fn to_config(&self, current: Option<&CalDavConfig>) -> Option<CalDavConfig> {
+        let url = self.url.clone().filter(|url| !url.is_empty())?;
+        let password = match self.password.as_deref() {
+            Some("") | None => current.and_then(|current| current.password.clone()),
+            Some(password) => Some(password.to_string()),
+        };
+        Some(CalDavConfig {
+            url,
+            collection: self.collection.clone().unwrap_or_default(),
+            username: self.username.clone().unwrap_or_default(),
+            password,
+            password_file: self
+                .password_file
+                .clone()
+                .filter(|file| !file.is_empty())
+                .map(manager::Job::fix_home),
+            component: self.component.unwrap_or_default(),
+        })
+    }
+}
 #[derive(FromForm)]
 struct NewJob {
     name: String,
     path: String,
     sync_to_google: bool,
     sync_interval: u64,
+    timeout: u64,
+    command: CommandForm,
+    retry: manager::RetryPolicy,
+    schedule: Schedule,
+    removed_policy: manager::RemovedPolicy,
+    caldav: CalDavForm,
 }
-#[delete("/<name>/delete")]
-fn delete_job(name: String) -> rocket::response::Redirect {
+#[derive(FromForm)]
+struct EditJob {
+    id: String,
+    path: String,
+    sync_to_google: bool,
+    sync_interval: u64,
+    timeout: u64,
+    command: CommandForm,
+    retry: manager::RetryPolicy,
+    schedule: Schedule,
+    removed_policy: manager::RemovedPolicy,
+    caldav: CalDavForm,
+}
+#[delete("/<id>/delete")]
+fn delete_job(id: String) -> rocket::response::Redirect {
     let mut manager = manager::Manager::from_save_file();
-    manager.data.jobs.retain(|job| job.name != name);
-    manager.save().unwrap();
+    if let Some(job) = manager.get_job_by_id(&id).cloned() {
+        manager.delete_job(&job.name).unwrap();
+    }
     rocket::response::Redirect::to("/jobs")
 }
 
 #[post("/edit", data = "<job>")]
-fn edit_job(job: Form<NewJob>) -> rocket::response::Redirect {
+fn edit_job(
+    job: Form<EditJob>,
+) -> Result<rocket::response::Redirect, rocket::response::status::BadRequest<String>> {
+    let schedule = job.schedule.clone().normalize();
+    schedule
+        .validate()
+        .map_err(rocket::response::status::BadRequest)?;
     let mut manager = manager::Manager::from_save_file();
-    if !manager.data.jobs.iter().any(|j| j.name == job.name) {
-        return rocket::response::Redirect::to(format!("/{}", job.name));
-    }
-    let current_job = manager
-        .data
-        .jobs
-        .iter_mut()
-        .find(|j| j.name == job.name)
-        .unwrap();
-    current_job.path = manager::Job::fix_home(job.path.clone());
-    current_job.sync_to_google = job.sync_to_google;
-    current_job.sync_interval = job.sync_interval;
-    current_job.next_sync = current_job.last_sync + current_job.sync_interval;
-    manager.save().unwrap();
-    rocket::response::Redirect::to(format!("/jobs"))
+    let current_job = match manager.data.jobs.iter_mut().find(|j| j.id == job.id) {
+        Some(v) => v,
+        None => return Ok(rocket::response::Redirect::to("/jobs")),
+    };
+    let mut edited = manager::Job::new(
+        current_job.name.clone(),
+        job.path.clone(),
+        job.sync_to_google,
+        job.sync_interval,
+    );
+    edited.command = job.command.to_command();
+    edited.timeout = job.timeout;
+    edited.retry = job.retry.clone();
+    edited.removed_policy = job.removed_policy;
+    edited.caldav = job.caldav.to_config(current_job.caldav.as_ref());
+    edited.schedule = schedule;
+    current_job.update_from(edited);
+    let current_job = current_job.clone();
+    manager.save_job(&current_job).unwrap();
+    crate::scheduler::wake();
+    Ok(rocket::response::Redirect::to(format!("/jobs")))
 }
 #[post("/new", data = "<job>")]
-fn new_job(job: Form<NewJob>) -> rocket::response::Redirect {
+fn new_job(
+    job: Form<NewJob>,
+) -> Result<rocket::response::Redirect, rocket::response::status::BadRequest<String>> {
+    let schedule = job.schedule.clone().normalize();
+    schedule
+        .validate()
+        .map_err(rocket::response::status::BadRequest)?;
     let mut manager = manager::Manager::from_save_file();
     if manager.data.jobs.iter().any(|j| j.name == job.name) {
-        return rocket::response::Redirect::to(format!("/jobs"));
+        return Ok(rocket::response::Redirect::to(format!("/jobs")));
     }
-    let job = manager::Job::new(
+    let mut new_job = manager::Job::new(
         job.name.clone(),
         format!("~/{}", job.path),
         job.sync_to_google,
         job.sync_interval,
     );
-    manager.add_job(job);
-    manager.save().unwrap();
-    rocket::response::Redirect::to(format!("/jobs"))
+    new_job.command = job.command.to_command();
+    new_job.timeout = job.timeout;
+    new_job.retry = job.retry.clone();
+    new_job.removed_policy = job.removed_policy;
+    new_job.caldav = job.caldav.to_config(None);
+    new_job.schedule = schedule;
+    let job = new_job;
+    manager.add_job(job.clone());
+    manager.save_job(&job).unwrap();
+    crate::scheduler::wake();
+    Ok(rocket::response::Redirect::to(format!("/jobs")))
 }
 
-#[post("/<name>/run")]
-fn run_job(name: String) -> &'static str {
+#[post("/<id>/run")]
+fn run_job(id: String) -> Result<&'static str, rocket::response::status::NotFound<String>> {
     let mut manager = manager::Manager::from_save_file();
-    manager
-        .data
-        .jobs
-        .iter_mut()
-        .find(|job| job.name == name)
-        .unwrap()
-        .next_sync = 0;
-    manager.save().unwrap();
-    println!("Request sent to run job {}", name);
-    "Request sent to run job"
+    let job = match manager.data.jobs.iter_mut().find(|job| job.id == id) {
+        Some(v) => v,
+        None => {
+            return Err(rocket::response::status::NotFound(
+                "Job not found".to_string(),
+            ))
+        }
+    };
+    job.next_sync = 0;
+    let job = job.clone();
+    manager.save_job(&job).unwrap();
+    crate::scheduler::wake();
+    println!("Request sent to run job {}", job.name);
+    Ok("Request sent to run job")
+}
+/// Lets a job that was paused after failing too often run again.
+#[post("/<id>/resume")]
+fn resume_job(id: String) -> Result<&'static str, rocket::response::status::NotFound<String>> {
+    let mut manager = manager::Manager::from_save_file();
+    let job = match manager.data.jobs.iter_mut().find(|job| job.id == id) {
+        Some(v) => v,
+        None => {
+            return Err(rocket::response::status::NotFound(
+                "Job not found".to_string(),
+            ))
+        }
+    };
+    job.resume();
+    let job = job.clone();
+    manager.save_job(&job).unwrap();
+    crate::scheduler::wake();
+    println!("Resumed job {}", job.name);
+    Ok("Job resumed")
 }
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PreviewPageData {
+    page: String,
+    job: manager::Job,
+    preview: manager::SyncPreview,
+    htmx_request: bool,
+}
+/// Runs the job without saving anything and shows what a sync would change.
+#[post("/<id>/dry_run")]
+async fn dry_run_job(
+    id: String,
+    headers: Headers,
+) -> Result<Template, rocket::response::status::Custom<String>> {
+    let manager = manager::Manager::read_no_save();
+    let job = match manager.get_job_by_id(&id) {
+        Some(job) => job.clone(),
+        None => {
+            return Err(rocket::response::status::Custom(
+                rocket::http::Status::NotFound,
+                "Job not found".to_string(),
+            ))
+        }
+    };
+    if crate::runner::is_running(&id) {
+        return Err(rocket::response::status::Custom(
+            rocket::http::Status::Conflict,
+            "Job is already running".to_string(),
+        ));
+    }
+    let page_data = PreviewPageData {
+        page: "jobs/preview".to_string(),
+        preview: job.dry_run(&manager).await,
+        job: job.redacted(),
+        htmx_request: headers.contains("hx-request".to_string()),
+    };
+    if page_data.htmx_request {
+        Ok(Template::render(
+            page_data.page.clone(),
+            context! {page_data},
+        ))
+    } else {
+        Ok(Template::render("layout", context! {page_data}))
+    }
+}
+/// Stops the job's current run. Doesn't need the save lock, so it works while the scheduler is
+/// busy running the job.
+#[post("/<id>/cancel")]
+fn cancel_job(id: String) -> Result<&'static str, rocket::response::status::Conflict<String>> {
+    if crate::runner::cancel(&id) {
+        println!("Request sent to cancel job {}", id);
+        Ok("Request sent to cancel job")
+    } else {
+        Err(rocket::response::status::Conflict(
+            "Job is not running".to_string(),
+        ))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProgressPageData {
+    job_id: String,
+    progress: Option<crate::protocol::Progress>,
+}
+/// Polled by the job view. Always rendered on its own since it only makes sense inside that page.
+#[get("/<id>/progress")]
+fn job_progress(id: String) -> Template {
+    let page_data = ProgressPageData {
+        progress: crate::runner::progress(&id),
+        job_id: id,
+    };
+    Template::render("jobs/progress", context! {page_data})
+}
+
 #[get("/<id>/<name>")]
 fn get_page_job(
     id: String,
     name: String,
     headers: Headers,
-) -> Result<Template, rocket::response::status::NotFound<String>> {
+) -> Result<PageResponse, rocket::response::status::NotFound<String>> {
     let manager = manager::Manager::read_no_save();
+    let job = match manager.get_job_by_id(&id) {
+        Some(job) => job.clone(),
+        None => {
+            // old links used the job name instead of the id
+            if let Some(job) = manager.jobs.iter().find(|job| job.name == id) {
+                return Ok(PageResponse::Redirect(
+                    rocket::response::Redirect::permanent(format!("/jobs/{}/{}", job.id, name)),
+                ));
+            }
+            return Err(rocket::response::status::NotFound(
+                "Job not found".to_string(),
+            ));
+        }
+    };
     let page_data = JobPageData {
         page: format!("jobs/{}", name).to_string(),
-        job: match manager.jobs.iter().find(|job| job.name == id) {
-            Some(job) => job.clone(),
-            None => {
-                return Err(rocket::response::status::NotFound(
-                    "Job not found".to_string(),
-                ))
-            }
-        },
-        manager,
+        runs: manager.get_runs(&id),
+        weekdays: WeekdayOption::all(&job.schedule),
+        job: job.redacted(),
+        running: crate::runner::is_running(&id),
+        manager: manager.redacted(),
         htmx_request: headers.contains("hx-request".to_string()),
     };
     if headers.contains("hx-request".to_string()) {
         let page = page_data.page.clone();
         println!("Rendering page: {}", page);
-        Ok(Template::render(page, context! {page_data}))
+        Ok(PageResponse::Page(Template::render(
+            page,
+            context! {page_data},
+        )))
     } else {
-        Ok(Template::render("layout", context! {page_data}))
+        Ok(PageResponse::Page(Template::render(
+            "layout",
+            context! {page_data},
+        )))
     }
 }
-#[post("/new_cli/<name>/<path>/<sync_to_google>/<sync_interval>")]
-fn new_job_cli(
+/// `program` defaults to `node index.js`. `args` and `env` (as `KEY=value`) can be repeated.
+/// With `dry_run` the job is run once and what it would change is returned as JSON, without
+/// adding it.
+#[post(
+    "/new_cli/<name>/<path>/<sync_to_google>/<sync_interval>?<program>&<args>&<working_dir>&<env>&<stdin>&<dry_run>"
+)]
+#[allow(clippy::too_many_arguments)]
+async fn new_job_cli(
     name: String,
     path: String,
     sync_to_google: bool,
     sync_interval: u64,
-) -> &'static str {
+    program: Option<String>,
+    args: Vec<String>,
+    working_dir: Option<String>,
+    env: Vec<String>,
+    stdin: Option<String>,
+    dry_run: bool,
+) -> String {
+    let mut job = manager::Job::new(name, format!("~/{}", path), sync_to_google, sync_interval);
+    job.command = manager::JobCommand::from_form(
+        program.as_deref().unwrap_or_default(),
+        &args.join("\n"),
+        working_dir.as_deref().unwrap_or_default(),
+        &env.join("\n"),
+        stdin.as_deref().unwrap_or_default(),
+    );
+    if dry_run {
+        let preview = job.dry_run(&manager::Manager::read_no_save()).await;
+        return serde_json::to_string_pretty(&preview).unwrap();
+    }
     let mut manager = manager::Manager::from_save_file();
-    let job = manager::Job::new(name, format!("~/{}", path), sync_to_google, sync_interval);
-    manager.add_job(job);
-    manager.save().unwrap();
-    "Job added"
+    manager.add_job(job.clone());
+    let job = manager
+        .data
+        .jobs
+        .iter()
+        .find(|j| j.name == job.name)
+        .unwrap();
+    manager.save_job(job).unwrap();
+    crate::scheduler::wake();
+    "Job added".to_string()
 }
 
 pub fn get_routes() -> Vec<rocket::Route> {
@@ -126,6 +394,10 @@ pub fn get_routes() -> Vec<rocket::Route> {
         new_job,
         new_job_cli,
         get_page_job,
-        run_job
+        run_job,
+        resume_job,
+        dry_run_job,
+        cancel_job,
+        job_progress
     ]
 }
@@ -1,15 +1,30 @@
-use std::{path::Path, path::PathBuf, sync::Arc};
+use std::{path::Path, path::PathBuf};
 
 use rocket::fs::{relative, FileServer, NamedFile};
 use rocket_dyn_templates::{context, Template};
 
+pub mod api;
 pub mod assignment;
+pub mod calendar;
+pub mod config;
+pub mod feed;
+pub mod health;
 pub mod job;
 pub mod manager;
 pub mod network;
+pub mod outbox;
+pub mod protocol;
+pub mod runner;
+pub mod schedule;
+pub mod scheduler;
+pub mod schema;
+pub mod storage;
 
 use network::*;
 
+/// Port the server listens on.
+pub const PORT: u16 = 6969;
+
 #[macro_use]
 extern crate rocket;
 
@@ -32,7 +47,7 @@ fn get_page(page: String, headers: Headers) -> Template {
     println!("Manager: {:?}", manager);
     let page_data = PageData {
         page: page.clone(),
-        manager,
+        manager: manager.redacted(),
         htmx_request: headers.contains("hx-request".to_string()),
     };
     if !page_data.htmx_request {
@@ -53,7 +68,7 @@ fn get_sub_page(folder: String, page: String, headers: Headers) -> Template {
     let manager = manager::Manager::read_no_save();
     let page_data = PageData {
         page: format!("{}/{}", folder, page),
-        manager,
+        manager: manager.redacted(),
         htmx_request: headers.contains("hx-request".to_string()),
     };
     if !page_data.htmx_request {
@@ -66,53 +81,29 @@ fn get_sub_page(folder: String, page: String, headers: Headers) -> Template {
 // #[launch]
 #[rocket::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let shutdown = Arc::new(std::sync::Mutex::new(false));
-    let thread_shutdown = shutdown.clone();
-    let thread = std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        loop {
-            let mut manager = manager::Manager::from_save_file();
-            manager.clear_past_due();
-            rt.block_on(manager.run_jobs());
-            // std::thread::sleep(std::time::Duration::from_secs(60));
-            manager.break_lock();
-            // println!("Waiting...");
-            let mut sleep_time = 10;
-            let sleep_duration = 1;
-            // sleep for 5 minutes
-            while sleep_time > 0 {
-                std::thread::sleep(std::time::Duration::from_secs(sleep_duration));
-                let val = thread_shutdown.lock().unwrap();
-                // println!("Checking for shutdown {}...", *val);
-                if *val {
-                    println!("Exiting thread...");
-                    return;
-                }
-                sleep_time -= sleep_duration;
-            }
-        }
-    });
-    _ = rocket::build()
+    manager::Manager::check_save_file()?;
+    let mut rocket = rocket::build();
+    if config::Config::get().calendar == config::CalendarKind::Mock {
+        println!("Using the mock calendar at {}", calendar::mock::MOUNT);
+        rocket = rocket.mount(calendar::mock::MOUNT, calendar::mock::get_routes());
+    }
+    _ = rocket
         .attach(Template::custom(|engines| {
             engines.handlebars.set_strict_mode(true);
         }))
-        .configure(rocket::Config::figment().merge(("port", 6969)))
+        .configure(rocket::Config::figment().merge(("port", PORT)))
+        .attach(scheduler::Scheduler::default())
         .mount("/", routes![index, get_page, get_sub_page, get_js])
         .mount("/assignments", assignment::get_routes())
         .mount("/jobs", job::get_routes())
+        .mount("/health", health::get_routes())
+        .mount("/feeds", feed::get_routes())
+        .mount("/api/v1", api::get_routes())
+        .register("/api/v1", api::get_catchers())
         .mount("/", FileServer::from(relative!("static/")))
         .launch()
         .await?;
 
-    println!("Shutting down...");
-    {
-        let mut val = shutdown.lock().unwrap();
-        *val = true;
-        println!("Waiting for thread to finish...");
-        drop(val);
-    }
-
-    thread.join().unwrap();
-    println!("Thread finished");
+    println!("Shut down");
     Ok(())
 }
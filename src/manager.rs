@@ -1,95 +1,517 @@
-use std::{cmp::Ordering, path::PathBuf};
+use std::{cmp::Ordering, collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
 
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use tokio::process::Command;
+use tokio::sync::Semaphore;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::calendar::RemoteEvent;
+use crate::config::{CalDavConfig, Config, StorageKind};
+use crate::outbox::OutboxEntry;
+use crate::runner::{self, RunOutcome};
+use crate::schedule::Schedule;
+use crate::schema;
+use crate::storage::{get_storage, JsonStorage, SqliteStorage};
+
+/// Newest script output version this build understands. See `ScriptOutput`.
+pub const SCRIPT_OUTPUT_VERSION: u32 = 2;
+
+/// One assignment as printed by a job's script. Everything past `name` is optional so scripts
+/// written for the original `course`, `due`, `name` format keep working.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
 pub struct ScriptAssignment {
     pub course: String,
     pub due: u64,
     pub name: String,
+    /// The source's own id for the assignment. When set it identifies the assignment instead of
+    /// its name and course, so renames upstream don't make a new one.
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub points: Option<f64>,
+    /// Homework, exam, quiz and so on. Free form.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Whether it has been handed in at the source.
+    #[serde(default)]
+    pub submitted: bool,
+    /// Due some time that day rather than at `due` exactly.
+    #[serde(default)]
+    pub all_day: bool,
 }
 impl ScriptAssignment {
     pub fn to_assignment(&self, job_name: String) -> Assignment {
-        Assignment::new(self.course.clone(), self.due, self.name.clone(), job_name)
+        let mut assignment =
+            Assignment::new(self.course.clone(), self.due, self.name.clone(), job_name);
+        assignment.external_id = self.external_id.clone().filter(|id| !id.is_empty());
+        assignment.url = self.url.clone();
+        assignment.description = self.description.clone();
+        assignment.points = self.points;
+        assignment.category = self.category.clone();
+        assignment.submitted = self.submitted;
+        assignment.all_day = self.all_day;
+        assignment
+    }
+}
+
+/// What a job's script prints on stdout. Version 1 is a bare list of assignments, later versions
+/// wrap the list as `{"version": 2, "assignments": [...]}`.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(untagged)]
+pub enum ScriptOutput {
+    Versioned {
+        version: u32,
+        assignments: Vec<ScriptAssignment>,
+    },
+    Legacy(Vec<ScriptAssignment>),
+}
+
+impl ScriptOutput {
+    pub fn parse(output: &[u8]) -> Result<Vec<ScriptAssignment>, String> {
+        match serde_json::from_slice(output).map_err(|e| e.to_string())? {
+            ScriptOutput::Legacy(assignments) => Ok(assignments),
+            ScriptOutput::Versioned {
+                version,
+                assignments,
+            } => {
+                if version > SCRIPT_OUTPUT_VERSION {
+                    return Err(format!(
+                        "Output is version {} but this build only understands up to {}",
+                        version, SCRIPT_OUTPUT_VERSION
+                    ));
+                }
+                Ok(assignments)
+            }
+        }
+    }
+}
+/// What a job runs. The process is started in `working_dir`, or in the job's `path` when that
+/// is not set, and gets `stdin` written to it if there is any.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct JobCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    #[schema(value_type = Option<String>)]
+    pub working_dir: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    pub stdin: Option<String>,
+}
+
+impl Default for JobCommand {
+    /// Jobs used to always be `node index.js`.
+    fn default() -> Self {
+        Self {
+            program: "node".to_string(),
+            args: vec!["index.js".to_string()],
+            working_dir: None,
+            env: BTreeMap::new(),
+            stdin: None,
+        }
+    }
+}
+
+impl JobCommand {
+    /// Builds a command from the job forms, where args are one per line and env vars are
+    /// `KEY=value` lines. Blank fields fall back to the defaults.
+    pub fn from_form(program: &str, args: &str, working_dir: &str, env: &str, stdin: &str) -> Self {
+        let working_dir = Some(working_dir.trim())
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Job::fix_home(dir.to_string()));
+        let program = program.trim();
+        if program.is_empty() {
+            return Self {
+                working_dir,
+                ..Self::default()
+            };
+        }
+        Self {
+            program: program.to_string(),
+            args: args
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect(),
+            working_dir,
+            env: env
+                .lines()
+                .filter_map(|line| line.trim().split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+                .collect(),
+            stdin: Some(stdin.to_string()).filter(|stdin| !stdin.is_empty()),
+        }
+    }
+}
+
+/// How the last run of a job ended.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Success,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+fn default_timeout() -> u64 {
+    5 * 60
+}
+
+/// What happens to an assignment a job returned before but stopped returning, like one the
+/// professor deleted.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, ToSchema, FromFormField,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovedPolicy {
+    /// Leave it as it is.
+    #[field(value = "keep")]
+    Keep,
+    /// Set `removed_upstream` so it shows up as removed.
+    #[default]
+    #[field(value = "flag")]
+    Flag,
+    /// Take it off the calendar and delete it.
+    #[field(value = "delete")]
+    Delete,
+}
+
+/// What to do when a run fails. Failed runs are retried sooner than `sync_interval`, waiting
+/// `backoff_base * 2^n` seconds up to `backoff_cap`, for `max_attempts` retries. After that the job
+/// goes back to its normal interval until it works again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema, FromForm)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Seconds before the first retry.
+    pub backoff_base: u64,
+    /// Longest wait between retries, in seconds.
+    pub backoff_cap: u64,
+    /// Up to this percent of the wait is added at random, so jobs that failed together don't
+    /// all retry at once.
+    pub jitter: u64,
+    /// Pause the job after this many failures in a row. 0 never pauses it.
+    pub pause_after: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff_base: 60,
+            backoff_cap: 60 * 60,
+            jitter: 10,
+            pause_after: 10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Seconds to wait before retrying after `failures` failures in a row, or None once the
+    /// retries are used up.
+    fn delay(&self, failures: u32) -> Option<u64> {
+        if failures == 0 || failures > self.max_attempts {
+            return None;
+        }
+        Some(backoff(
+            self.backoff_base,
+            self.backoff_cap,
+            self.jitter,
+            failures,
+        ))
     }
 }
-#[derive(Serialize, Deserialize, Debug, Clone)]
+
+/// `base` doubled for every failure after the first, up to `cap`, plus up to `jitter` percent
+/// at random.
+pub fn backoff(base: u64, cap: u64, jitter: u64, failures: u32) -> u64 {
+    let delay = base
+        .saturating_mul(
+            1u64.checked_shl(failures.saturating_sub(1))
+                .unwrap_or(u64::MAX),
+        )
+        .min(cap);
+    let jitter = delay.saturating_mul(jitter.min(100)) / 100;
+    delay + rand::random::<u64>() % (jitter + 1)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Job {
+    pub id: String,
     pub name: String,
+    #[schema(value_type = String)]
     pub path: PathBuf,
+    pub command: JobCommand,
     pub sync_to_google: bool,
     pub next_sync: u64,
     pub last_sync: u64,
     pub sync_interval: u64,
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Seconds a run may take before it is killed. 0 lets it run forever.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub last_status: Option<RunStatus>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Failed or timed out runs since the last one that worked.
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// Set after `retry.pause_after` failures in a row. Paused jobs don't run until resumed.
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub removed_policy: RemovedPolicy,
+    /// Ids of the assignments the last successful run returned, to tell which ones went missing.
+    #[serde(default)]
+    pub last_output: Vec<String>,
+    /// Sends this job's assignments to its own CalDAV calendar instead of the configured one.
+    #[serde(default)]
+    pub caldav: Option<CalDavConfig>,
 }
 
 impl Job {
+    /// The job without its CalDAV password, for API responses and pages.
+    pub fn redacted(mut self) -> Self {
+        if let Some(caldav) = &mut self.caldav {
+            caldav.password = None;
+        }
+        self
+    }
     pub fn fix_home(path: String) -> PathBuf {
         PathBuf::from(path.replace("~", home::home_dir().unwrap().to_str().unwrap()))
     }
     pub fn new(name: String, path: String, sync_to_google: bool, sync_interval: u64) -> Self {
         Self {
+            id: new_id(),
             name,
             path: Self::fix_home(path),
+            command: JobCommand::default(),
             sync_to_google,
             next_sync: 0,
             last_sync: 0,
             sync_interval,
+            schedule: Schedule::default(),
+            timeout: default_timeout(),
+            last_status: None,
+            retry: RetryPolicy::default(),
+            consecutive_failures: 0,
+            paused: false,
+            removed_policy: RemovedPolicy::default(),
+            last_output: Vec::new(),
+            caldav: None,
         }
     }
     fn sync_due(&self) -> bool {
-        self.next_sync * 1000
-            < std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64
+        !self.paused
+            && self.next_sync * 1000
+                < std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64
     }
 
-    async fn sync(&mut self) -> Result<Vec<Assignment>, Box<dyn std::error::Error>> {
-        self.last_sync = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        self.next_sync = self.last_sync + self.sync_interval;
+    /// Runs the job and records how it went. Failures end up in the returned run instead of being
+    /// returned as errors, so one broken scraper can't stop the others.
+    async fn sync(&mut self) -> (JobRun, Vec<Assignment>) {
+        self.last_sync = now();
         println!("Starting sync for {}", self.name);
+        let mut run = JobRun::new(&self.id, self.last_sync);
 
-        let output = match Command::new("node")
-            .arg("index.js")
-            .current_dir(&self.path)
-            .output()
-            .await
-        {
-            Ok(output) => output,
+        let timeout = Some(Duration::from_secs(self.timeout)).filter(|t| !t.is_zero());
+        let assignments = match runner::run(&self.id, &self.command, &self.path, timeout).await {
+            Ok(RunOutcome::Finished(output, stream)) => {
+                run.exit_code = output.status.code();
+                run.stdout = tail(&output.stdout);
+                run.stderr = tail(&output.stderr);
+                if output.status.success() {
+                    match stream.finish(&output.stdout) {
+                        Ok(assignments) => {
+                            run.status = RunStatus::Success;
+                            assignments
+                                .into_iter()
+                                .map(|a| a.to_assignment(self.name.clone()))
+                                .collect()
+                        }
+                        Err(e) => {
+                            run.error = Some(e);
+                            Vec::new()
+                        }
+                    }
+                } else {
+                    run.error = Some(format!("Script failed with {}", output.status));
+                    Vec::new()
+                }
+            }
+            Ok(RunOutcome::TimedOut) => {
+                run.status = RunStatus::TimedOut;
+                run.error = Some(format!("Timed out after {}s", self.timeout));
+                Vec::new()
+            }
+            Ok(RunOutcome::Cancelled) => {
+                run.status = RunStatus::Cancelled;
+                Vec::new()
+            }
             Err(e) => {
-                println!("Error syncing {}", self.name);
-                return Err(Box::new(e));
+                run.error = Some(format!("Could not start: {}", e));
+                Vec::new()
             }
         };
+        run.finished_at = now();
+        run.assignments = assignments.len();
+        match &run.error {
+            Some(error) => println!("Sync for {} failed: {}", self.name, error),
+            None => println!("Done syncing {}", self.name),
+        }
+        self.last_status = Some(run.status);
+        self.schedule_next(run.status);
+        (run, assignments)
+    }
 
-        println!("Done syncing {}", self.name);
-        if output.status.success() {
-            println!("Synced {}", self.name);
-            println!("{}", String::from_utf8(output.stdout.clone())?);
-            let stdout = String::from_utf8(output.stdout)?;
-            let assignments: Vec<ScriptAssignment> = match serde_json::from_str(&stdout) {
-                Ok(v) => v,
-                _ => vec![],
-            };
-            let assignments = assignments
-                .into_iter()
-                .map(|a| a.to_assignment(self.name.clone()))
-                .collect();
-            return Ok(assignments);
+    /// Sets `next_sync` from how the run went, and pauses the job once it has failed too often.
+    fn schedule_next(&mut self, status: RunStatus) {
+        self.reschedule();
+        match status {
+            RunStatus::Success => self.consecutive_failures = 0,
+            // a cancelled run says nothing about whether the job works
+            RunStatus::Cancelled => {}
+            RunStatus::Failed | RunStatus::TimedOut => {
+                self.consecutive_failures += 1;
+                if self.retry.pause_after != 0
+                    && self.consecutive_failures >= self.retry.pause_after
+                {
+                    println!(
+                        "Pausing {} after {} failures in a row",
+                        self.name, self.consecutive_failures
+                    );
+                    self.paused = true;
+                } else if let Some(delay) = self.retry.delay(self.consecutive_failures) {
+                    println!("Retrying {} in {}s", self.name, delay);
+                    self.next_sync = self.schedule.allowed_from(now() + delay);
+                }
+            }
         }
+    }
+
+    /// Runs a copy of the job and works out what syncing it would change, without saving
+    /// anything or pushing to the calendar.
+    pub async fn dry_run(&self, data: &ManagerData) -> SyncPreview {
+        // registered under the job id like a real run, so it can be watched and cancelled the
+        // same way, and the scheduler waits for it before running the job for real
+        let (run, assignments) = self.clone().sync().await;
+        data.preview(self, run, assignments)
+    }
 
-        Ok(Vec::new())
+    /// Copies what a run changed from the copy of the job it ran on, leaving anything edited in
+    /// the meantime alone.
+    fn take_run_state(&mut self, ran: &Job) {
+        self.last_sync = ran.last_sync;
+        self.next_sync = ran.next_sync;
+        self.last_status = ran.last_status;
+        self.consecutive_failures = ran.consecutive_failures;
+        self.paused = ran.paused;
+    }
+
+    /// Records a run that died before it could record itself.
+    fn failed(&mut self, error: String) -> JobRun {
+        self.last_sync = now();
+        let mut run = JobRun::new(&self.id, self.last_sync);
+        run.error = Some(error);
+        self.last_status = Some(RunStatus::Failed);
+        self.schedule_next(RunStatus::Failed);
+        run
+    }
+
+    /// Takes the settings of `edited`, for when a job gets saved again from a form or the CLI.
+    /// Its run history is kept, and so is the working directory when `edited` leaves it empty.
+    /// A retry that is already planned is only moved when the schedule changed.
+    pub fn update_from(&mut self, edited: Job) {
+        let reschedule =
+            self.schedule != edited.schedule || self.sync_interval != edited.sync_interval;
+        let working_dir = self.command.working_dir.take();
+        self.path = edited.path;
+        self.command = edited.command;
+        if self.command.working_dir.is_none() {
+            self.command.working_dir = working_dir;
+        }
+        self.timeout = edited.timeout;
+        self.retry = edited.retry;
+        self.sync_to_google = edited.sync_to_google;
+        self.sync_interval = edited.sync_interval;
+        self.schedule = edited.schedule;
+        self.removed_policy = edited.removed_policy;
+        self.caldav = edited.caldav;
+        if reschedule {
+            self.reschedule();
+        }
+    }
+
+    /// Works out `next_sync` from the last run, for when the run finished or the schedule changed.
+    pub fn reschedule(&mut self) {
+        self.next_sync = self.schedule.next_run(self.last_sync, self.sync_interval);
+    }
+
+    /// Lets a paused job run again, starting right away.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.consecutive_failures = 0;
+        self.next_sync = 0;
+    }
+}
+
+/// Bytes of stdout and stderr kept with each run.
+const OUTPUT_TAIL: usize = 4096;
+
+/// The end of a script's output, which is where the error usually is.
+fn tail(output: &[u8]) -> String {
+    String::from_utf8_lossy(&output[output.len().saturating_sub(OUTPUT_TAIL)..]).into_owned()
+}
+
+/// One run of a job. Each job keeps its last `run_history` runs.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct JobRun {
+    pub id: String,
+    pub job_id: String,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub status: RunStatus,
+    /// Not set when the process was killed or never started.
+    pub exit_code: Option<i32>,
+    /// Number of assignments read from the output.
+    pub assignments: usize,
+    /// Why the run failed, including output that wasn't a list of assignments.
+    pub error: Option<String>,
+    /// The last few KB of stdout.
+    pub stdout: String,
+    /// The last few KB of stderr.
+    pub stderr: String,
+}
+
+impl JobRun {
+    pub fn new(job_id: &str, started_at: u64) -> Self {
+        Self {
+            id: new_id(),
+            job_id: job_id.to_string(),
+            started_at,
+            finished_at: started_at,
+            status: RunStatus::Failed,
+            exit_code: None,
+            assignments: 0,
+            error: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Assignment {
+    pub id: String,
     pub course: String,
     pub due: u64,
     pub name: String,
@@ -97,20 +519,64 @@ pub struct Assignment {
     pub synced: Option<bool>,
     pub link_name: String,
     pub done: bool,
+    /// When the assignment was last marked done.
+    #[serde(default)]
+    pub completed_at: Option<u64>,
+    /// Set once the assignment is past due and has moved to the archive.
+    #[serde(default)]
+    pub archived_at: Option<u64>,
+    /// Overdue assignments stay on the main list until they are done or dismissed.
+    #[serde(default)]
+    pub dismissed: bool,
+    /// When the job that made it stopped returning it.
+    #[serde(default)]
+    pub removed_upstream: Option<u64>,
+    /// The event each calendar backend made for it, by backend name. Updates and deletes go to
+    /// that event instead of looking it up by name.
+    #[serde(default)]
+    pub remote_events: BTreeMap<String, RemoteEvent>,
+    /// The rest come from the script, see `ScriptAssignment`.
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub points: Option<f64>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub submitted: bool,
+    #[serde(default)]
+    pub all_day: bool,
 }
 
+/// Assignments from the same job are the same one if their external ids match, or, when either
+/// doesn't have one, if their name and course do.
 impl PartialEq for Assignment {
     fn eq(&self, other: &Self) -> bool {
-        self.course == other.course && self.name == other.name && self.job_name == other.job_name
+        if self.job_name != other.job_name {
+            return false;
+        }
+        match (&self.external_id, &other.external_id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            _ => self.course == other.course && self.name == other.name,
+        }
     }
 }
 
 impl Assignment {
+    /// Identity used by storage backends that keep one row per assignment.
+    pub fn key(&self) -> String {
+        self.id.clone()
+    }
     pub fn get_link_name(name: String) -> String {
         name.replace(" ", "-").replace("/", "-").replace(".", "-")
     }
     pub fn new(course: String, due: u64, name: String, job_name: String) -> Self {
         Self {
+            id: new_id(),
             course,
             due,
             name: name.clone(),
@@ -118,6 +584,52 @@ impl Assignment {
             synced: None,
             link_name: Self::get_link_name(name),
             done: false,
+            completed_at: None,
+            archived_at: None,
+            dismissed: false,
+            removed_upstream: None,
+            remote_events: BTreeMap::new(),
+            external_id: None,
+            url: None,
+            description: None,
+            points: None,
+            category: None,
+            submitted: false,
+            all_day: false,
+        }
+    }
+    /// Whether a freshly scraped copy of this assignment says anything different about it.
+    pub fn details_changed(&self, scraped: &Assignment) -> bool {
+        self.due != scraped.due
+            || self.name != scraped.name
+            || self.course != scraped.course
+            || self.external_id != scraped.external_id
+            || self.url != scraped.url
+            || self.description != scraped.description
+            || self.points != scraped.points
+            || self.category != scraped.category
+            || self.submitted != scraped.submitted
+            || self.all_day != scraped.all_day
+    }
+    /// Takes what a freshly scraped copy says about the assignment. Local state like being done,
+    /// dismissed or on the calendar is kept, the scraped copy doesn't know about it.
+    pub fn update_from(&mut self, scraped: Assignment) {
+        self.name = scraped.name;
+        self.link_name = scraped.link_name;
+        self.course = scraped.course;
+        self.due = scraped.due;
+        self.job_name = scraped.job_name;
+        self.external_id = scraped.external_id;
+        self.url = scraped.url;
+        self.description = scraped.description;
+        self.points = scraped.points;
+        self.category = scraped.category;
+        self.submitted = scraped.submitted;
+        self.all_day = scraped.all_day;
+        self.synced = None;
+        // moved out of the past, so it belongs back on the main list
+        if !self.past_due() {
+            self.archived_at = None;
         }
     }
     pub fn past_due(&self) -> bool {
@@ -127,8 +639,20 @@ impl Assignment {
                 .unwrap()
                 .as_millis() as u64
     }
+    /// Clearing `synced` makes the next sync push the change to the calendar.
     pub fn mark_done(&mut self) {
         self.done = true;
+        self.completed_at = Some(now());
+        self.synced = None;
+    }
+    /// Archived but neither done nor dismissed.
+    pub fn overdue(&self) -> bool {
+        self.archived_at.is_some() && !self.done && !self.dismissed
+    }
+    pub fn mark_undone(&mut self) {
+        self.done = false;
+        self.completed_at = None;
+        self.synced = None;
     }
     pub fn fix_link_name(&self) -> Assignment {
         let mut ret = self.clone();
@@ -137,13 +661,59 @@ impl Assignment {
     }
 }
 
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Opaque id for new jobs and assignments. It never changes, even when the name does.
+pub fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// An assignment whose due date moved.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct DueChange {
+    pub assignment: Assignment,
+    pub previous_due: u64,
+}
+
+/// What a sync would do, from a dry run. Nothing in it has been saved.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SyncPreview {
+    pub run: JobRun,
+    pub new: Vec<Assignment>,
+    pub changed: Vec<DueChange>,
+    pub unchanged: Vec<Assignment>,
+    /// Upcoming assignments from this job that the script didn't return this time.
+    pub disappeared: Vec<Assignment>,
+}
+
 static SAVE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
+/// The lock only guards the save data on disk, which is still whole after a panic, so a poisoned
+/// lock is taken anyway instead of taking every page down with it.
+fn lock_save() -> std::sync::MutexGuard<'static, ()> {
+    SAVE_LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ManagerData {
     pub jobs: Vec<Job>,
     pub assignments: Vec<Assignment>,
     pub save_file: PathBuf,
+    #[serde(default)]
+    pub schema_version: u64,
+    /// Oldest first.
+    #[serde(default)]
+    pub runs: Vec<JobRun>,
+    /// Calendar pushes that haven't been confirmed yet, oldest first.
+    #[serde(default)]
+    pub outbox: Vec<OutboxEntry>,
 }
 
 #[derive(Debug)]
@@ -156,8 +726,14 @@ impl Manager {
     pub fn get_assignment_from_link_name(&self, name: String) -> Option<&Assignment> {
         self.data.get_assignment_from_link_name(name)
     }
+    pub fn get_assignment(&self, id: &str) -> Option<&Assignment> {
+        self.data.get_assignment(id)
+    }
+    pub fn get_job_by_id(&self, id: &str) -> Option<&Job> {
+        self.data.get_job_by_id(id)
+    }
     pub fn from_save_file() -> Self {
-        let lock = SAVE_LOCK.lock().unwrap();
+        let lock = lock_save();
         let data = ManagerData::from_save_file();
         Self {
             data,
@@ -165,7 +741,7 @@ impl Manager {
         }
     }
     pub fn empty() -> Self {
-        let lock = SAVE_LOCK.lock().unwrap();
+        let lock = lock_save();
         let data = ManagerData::empty();
         Self {
             data,
@@ -181,6 +757,44 @@ impl Manager {
         }
         self.data.save()
     }
+    /// Writes one job through the storage backend instead of saving everything.
+    pub fn save_job(&self, job: &Job) -> Result<(), std::io::Error> {
+        if self.lock.is_none() {
+            return Err(std::io::Error::other("Lock is none"));
+        }
+        get_storage()?.save_job(&self.data, job)
+    }
+    /// Writes one assignment through the storage backend instead of saving everything.
+    pub fn save_assignment(&self, assignment: &Assignment) -> Result<(), std::io::Error> {
+        if self.lock.is_none() {
+            return Err(std::io::Error::other("Lock is none"));
+        }
+        get_storage()?.save_assignment(&self.data, assignment)
+    }
+    pub fn delete_job(&mut self, name: &str) -> Result<(), std::io::Error> {
+        if self.lock.is_none() {
+            return Err(std::io::Error::other("Lock is none"));
+        }
+        self.data.remove_job(name);
+        get_storage()?.remove_job(&self.data, name)
+    }
+    pub fn delete_assignment(&mut self, assignment: &Assignment) -> Result<(), std::io::Error> {
+        if self.lock.is_none() {
+            return Err(std::io::Error::other("Lock is none"));
+        }
+        self.data.assignments.retain(|a| a.id != assignment.id);
+        // a change still waiting to be pushed would put it back on the calendar
+        self.data
+            .outbox
+            .retain(|entry| entry.assignment_id != assignment.id);
+        if !assignment.remote_events.is_empty() {
+            let mut gone = assignment.clone();
+            // done assignments are taken off the calendar
+            gone.done = true;
+            crate::outbox::enqueue(&mut self.data, &gone, false);
+        }
+        get_storage()?.remove_assignment(&self.data, assignment)
+    }
     pub fn add_job(&mut self, job: Job) {
         if self.lock.is_none() {
             return;
@@ -200,11 +814,19 @@ impl Manager {
         }
         self.data.remove_job(name);
     }
-    pub fn clear_past_due(&mut self) {
+    pub fn archive_past_due(&mut self) {
         if self.lock.is_none() {
             return;
         }
-        self.data.clear_past_due();
+        self.data.archive_past_due();
+    }
+    pub fn dismiss(&mut self, id: &str, dismissed: bool) {
+        if self.lock.is_none() {
+            return;
+        }
+        if let Some(assignment) = self.data.assignments.iter_mut().find(|a| a.id == id) {
+            assignment.dismissed = dismissed;
+        }
     }
     pub fn should_sync(&self) -> bool {
         if self.lock.is_none() {
@@ -212,52 +834,83 @@ impl Manager {
         }
         self.data.should_sync()
     }
-    pub async fn run_jobs(&mut self) {
+    /// One pass of the scheduler. Due jobs run at the same time, up to `max_concurrent_jobs`,
+    /// without holding the save lock. The lock is only taken to merge their results, so edits
+    /// made while the jobs run are kept.
+    pub async fn run_due_jobs() -> Result<(), std::io::Error> {
+        let due = Self::read_no_save().due_jobs();
+        let results = run_all(due).await;
+
+        {
+            let mut manager = Self::from_save_file();
+            manager.data.merge_results(results);
+            manager.save()?;
+        }
+        crate::outbox::drain().await
+    }
+    pub fn mark_done(&mut self, id: &str) {
         if self.lock.is_none() {
             return;
         }
-        self.data.run_jobs().await;
+        self.data.mark_done(id);
     }
-    pub fn mark_done(&mut self, link_name: String) {
+    pub fn mark_undone(&mut self, id: &str) {
         if self.lock.is_none() {
             return;
         }
-        self.data.mark_done(link_name);
+        self.data.mark_undone(id);
     }
     pub fn break_lock(&mut self) {
         self.lock = None;
     }
 
+    /// Makes sure the storage opens and its data can be loaded by this build. Used at startup so
+    /// a save file from a newer version, or a database that can't be opened, stops the server
+    /// instead of being replaced.
+    pub fn check_save_file() -> Result<(), std::io::Error> {
+        let _lock = lock_save();
+        get_storage()?;
+        match ManagerData::try_from_save_file() {
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     pub fn read_no_save() -> ManagerData {
-        let _lock = SAVE_LOCK.lock().unwrap();
+        let _lock = lock_save();
         ManagerData::from_save_file()
     }
 }
 
 impl ManagerData {
+    /// The data with every job [`Job::redacted`], for pages.
+    pub fn redacted(mut self) -> Self {
+        self.jobs = self.jobs.into_iter().map(Job::redacted).collect();
+        self
+    }
     fn get_save_file() -> PathBuf {
-        let home_dir = home::home_dir().unwrap();
-        PathBuf::from(home_dir.join(".calendarthing/manager.json"))
+        match Config::get().storage {
+            StorageKind::Json => JsonStorage::get_save_file(),
+            StorageKind::Sqlite => SqliteStorage::get_save_file(),
+        }
     }
-    fn empty() -> Self {
+    pub(crate) fn empty() -> Self {
         Self {
             jobs: Vec::new(),
             assignments: Vec::new(),
-            save_file: PathBuf::from(Self::get_save_file()),
+            save_file: Self::get_save_file(),
+            schema_version: schema::SCHEMA_VERSION,
+            runs: Vec::new(),
+            outbox: Vec::new(),
         }
     }
 
     fn try_from_save_file() -> Result<Self, std::io::Error> {
-        let save_file = Self::get_save_file();
-        let file = std::fs::read_to_string(save_file)?;
-        Ok(serde_json::from_str(&file)?)
+        get_storage()?.load()
     }
 
     fn save(&self) -> Result<(), std::io::Error> {
-        let save_file = Self::get_save_file();
-        let json = serde_json::to_string(self)?;
-        std::fs::create_dir_all(save_file.parent().unwrap())?;
-        std::fs::write(save_file, json)
+        get_storage()?.save(self)
     }
 
     fn new_to_file() -> Result<Self, std::io::Error> {
@@ -267,14 +920,19 @@ impl ManagerData {
     }
 
     fn from_save_file() -> Self {
-        let save_file = Self::get_save_file();
-        if !save_file.exists() {
-            println!("Creating new save file");
-            return Self::new_to_file().unwrap();
-        }
         let mut ret = match Self::try_from_save_file() {
             Ok(manager) => manager,
-            Err(_) => Self::new_to_file().unwrap(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("Creating new save file");
+                return Self::new_to_file().unwrap();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                panic!("Refusing to touch save data: {}", e);
+            }
+            Err(e) => {
+                println!("Could not load save data, starting over: {}", e);
+                return Self::new_to_file().unwrap();
+            }
         };
         ret.assignments.sort_by(|a, b| {
             if a.due < b.due {
@@ -285,12 +943,9 @@ impl ManagerData {
                 Ordering::Equal
             }
         });
-        ret.assignments = ret
-            .assignments
-            .iter()
-            .map(|a| a.fix_link_name())
-            .filter(|a| !a.past_due())
-            .collect();
+        ret.assignments = ret.assignments.iter().map(|a| a.fix_link_name()).collect();
+        // readers see the archive state right away, the scheduler is the one that saves it
+        ret.archive_past_due();
         // _ = ret.save();
         ret
     }
@@ -298,33 +953,84 @@ impl ManagerData {
     fn add_job(&mut self, job: Job) {
         for j in &mut self.jobs {
             if j.name == job.name {
-                j.path = job.path;
-                j.sync_to_google = job.sync_to_google;
-                j.sync_interval = job.sync_interval;
-                j.next_sync = j.last_sync + j.sync_interval;
+                j.update_from(job);
                 return;
             }
         }
         self.jobs.push(job);
     }
 
-    fn add_assignment(&mut self, assignment: Assignment) {
-        if self.assignments.contains(&assignment) {
-            self.assignments.retain(|a| a != &assignment);
+    pub(crate) fn add_assignment(&mut self, assignment: Assignment) {
+        match self.assignments.iter_mut().find(|a| **a == assignment) {
+            // scraped assignments come in with a fresh id and none of the local state, so the
+            // one we already have only takes their details
+            Some(existing) => existing.update_from(assignment),
+            None => self.assignments.push(assignment),
         }
-        self.assignments.push(assignment);
     }
 
     fn remove_job(&mut self, name: &str) {
+        if let Some(job) = self.get_job(name.to_string()) {
+            let id = job.id.clone();
+            self.runs.retain(|run| run.job_id != id);
+        }
         self.jobs.retain(|job| job.name != name);
     }
 
-    fn clear_past_due(&mut self) {
+    /// Records a run and drops the job's runs past `run_history`.
+    fn add_run(&mut self, run: JobRun) {
+        let job_id = run.job_id.clone();
+        self.runs.push(run);
+        let count = self.runs.iter().filter(|r| r.job_id == job_id).count();
+        let mut excess = count.saturating_sub(Config::get().run_history);
+        self.runs.retain(|r| {
+            if excess > 0 && r.job_id == job_id {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Runs of a job, newest first.
+    pub fn get_runs(&self, job_id: &str) -> Vec<JobRun> {
+        self.runs
+            .iter()
+            .rev()
+            .filter(|run| run.job_id == job_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Moves past due assignments into the archive and drops archived ones that are older than
+    /// `archive_retention` days.
+    fn archive_past_due(&mut self) {
+        let now = now();
+        let mut archived = 0;
+        for assignment in &mut self.assignments {
+            if assignment.archived_at.is_none() && assignment.past_due() {
+                assignment.archived_at = Some(now);
+                archived += 1;
+            }
+        }
+        if archived != 0 {
+            println!("Archived {} past due assignments", archived);
+        }
+        let retention = Config::get().archive_retention;
+        if retention == 0 {
+            return;
+        }
         let start_len = self.assignments.len();
-        self.assignments.retain(|assignment| !assignment.past_due());
+        self.assignments.retain(|assignment| {
+            // overdue work is kept until someone deals with it
+            assignment.overdue()
+                || assignment
+                    .archived_at
+                    .is_none_or(|_| assignment.due + retention * 24 * 60 * 60 > now)
+        });
         if start_len != self.assignments.len() {
             println!(
-                "Cleared {} past due assignments",
+                "Removed {} assignments past the archive retention",
                 start_len - self.assignments.len()
             );
         }
@@ -336,7 +1042,7 @@ impl ManagerData {
             let mut found = false;
             for a in &self.assignments {
                 if *a == *assignment {
-                    if a.due != assignment.due {
+                    if a.details_changed(assignment) {
                         changed.push(assignment.clone());
                     }
                     found = true;
@@ -350,6 +1056,94 @@ impl ManagerData {
         changed
     }
 
+    fn preview(&self, job: &Job, run: JobRun, assignments: Vec<Assignment>) -> SyncPreview {
+        let changed = self.get_changed_assignments(assignments.clone());
+        let mut preview = SyncPreview {
+            run,
+            new: Vec::new(),
+            changed: Vec::new(),
+            unchanged: Vec::new(),
+            disappeared: Vec::new(),
+        };
+        for mut assignment in assignments {
+            match self.assignments.iter().find(|a| **a == assignment) {
+                Some(existing) if changed.contains(&assignment) => {
+                    assignment.id = existing.id.clone();
+                    preview.changed.push(DueChange {
+                        assignment,
+                        previous_due: existing.due,
+                    });
+                }
+                Some(existing) => preview.unchanged.push(existing.clone()),
+                None => preview.new.push(assignment),
+            }
+        }
+        // a run that failed or was cancelled says nothing about what disappeared
+        if preview.run.status == RunStatus::Success {
+            preview.disappeared = self.get_disappeared(job, &preview);
+        }
+        preview
+    }
+
+    fn get_disappeared(&self, job: &Job, preview: &SyncPreview) -> Vec<Assignment> {
+        let returned: Vec<&Assignment> = preview
+            .new
+            .iter()
+            .chain(preview.changed.iter().map(|change| &change.assignment))
+            .chain(preview.unchanged.iter())
+            .collect();
+        self.get_missing(job, &returned)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Upcoming assignments from the job's last output that aren't in `returned`. Past due ones
+    /// are left out since scrapers usually stop returning those.
+    fn get_missing(&self, job: &Job, returned: &[&Assignment]) -> Vec<&Assignment> {
+        self.assignments
+            .iter()
+            .filter(|a| job.last_output.contains(&a.id))
+            .filter(|a| a.archived_at.is_none() && a.removed_upstream.is_none())
+            .filter(|a| !returned.contains(a))
+            .collect()
+    }
+
+    /// Remembers what a successful run returned and applies the job's `removed_policy` to what it
+    /// didn't return anymore.
+    fn track_output(&mut self, job_id: &str, returned: &[Assignment]) {
+        let Some(job) = self.get_job_by_id(job_id) else {
+            return;
+        };
+        let returned: Vec<&Assignment> = returned.iter().collect();
+        let missing: Vec<String> = self
+            .get_missing(job, &returned)
+            .iter()
+            .map(|a| a.id.clone())
+            .collect();
+        let policy = job.removed_policy;
+        let name = job.name.clone();
+        let mut output = Vec::new();
+        let now = now();
+        for assignment in &mut self.assignments {
+            if returned.contains(&&*assignment) {
+                // it came back
+                assignment.removed_upstream = None;
+                output.push(assignment.id.clone());
+            } else if missing.contains(&assignment.id) && policy != RemovedPolicy::Keep {
+                println!("{} was removed from {}", assignment.name, name);
+                assignment.removed_upstream = Some(now);
+                if policy == RemovedPolicy::Delete {
+                    // the push takes it off the calendar, then it is deleted
+                    assignment.synced = None;
+                }
+            }
+        }
+        if let Some(job) = self.jobs.iter_mut().find(|job| job.id == job_id) {
+            job.last_output = output;
+        }
+    }
+
     fn get_job(&self, name: String) -> Option<&Job> {
         self.jobs.iter().find(|job| job.name == name)
     }
@@ -363,124 +1157,287 @@ impl ManagerData {
         false
     }
 
-    async fn sync_assignment_to_google(&mut self, assignment: &mut Assignment) {
-        println!("Syncing {} to google", assignment.name);
-        let actual_assignment = self
-            .assignments
-            .iter_mut()
-            .find(|v| v.name == assignment.name)
-            .unwrap_or(assignment);
-
-        if actual_assignment.synced != None {
-            return;
-        }
-        let dir = std::env::current_exe()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("google_sync");
-        println!("Dir: {:?}", dir);
-        let output = Command::new("node")
-            .arg("google.js")
-            .arg(serde_json::to_string(assignment).unwrap())
-            .current_dir(dir)
-            .output()
-            .await
-            .unwrap();
-        println!("Output: {}", String::from_utf8(output.stdout).unwrap());
-        println!("Synced {} to google", assignment.name);
-    }
-
-    async fn sync_to_google(&mut self, mut assignments: Vec<Assignment>) {
-        for assignment in &mut assignments {
-            if let Some(job_name) = &assignment.job_name {
-                if let Some(job) = self.get_job(job_name.clone()) {
-                    if job.sync_to_google {
-                        self.sync_assignment_to_google(assignment).await;
-                    }
-                } else {
-                    self.sync_assignment_to_google(assignment).await;
-                }
-            }
-        }
+    /// Only used to redirect old `/assignments/<link name>/...` urls, since link names can
+    /// collide. Everything else should go through `get_assignment`.
+    pub fn get_assignment_from_link_name(&self, name: String) -> Option<&Assignment> {
+        self.assignments
+            .iter()
+            .find(|assignment| Assignment::get_link_name(assignment.name.clone()) == name)
+    }
+    pub fn get_assignment(&self, id: &str) -> Option<&Assignment> {
+        self.assignments
+            .iter()
+            .find(|assignment| assignment.id == id)
+    }
+    pub fn get_job_by_id(&self, id: &str) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
     }
 
-    fn assignment_match_link_name2(name1: &String, name2: &String, i: usize) -> bool {
-        if i >= name1.len() {
-            return true;
-        }
-        let left_char = name1.as_str().chars().nth(i).unwrap();
-        let right_char = name2.as_str().chars().nth(i).unwrap();
-        if left_char != right_char
-            && left_char != '.'
-            && left_char != ' '
-            && left_char != '/'
-            && right_char != '-'
-        {
-            return false;
+    fn mark_done(&mut self, id: &str) {
+        if let Some(assignment) = self.assignments.iter_mut().find(|a| a.id == id) {
+            assignment.mark_done();
         }
-        Self::assignment_match_link_name2(name1, name2, i + 1)
     }
-    fn assignment_match_link_name(name1: &String, name2: &String) -> bool {
-        if name1.len() != name2.len() {
-            return false;
+    fn mark_undone(&mut self, id: &str) {
+        if let Some(assignment) = self.assignments.iter_mut().find(|a| a.id == id) {
+            assignment.mark_undone();
         }
-        Self::assignment_match_link_name2(name1, name2, 0)
     }
-    pub fn get_assignment_from_link_name(&self, name: String) -> Option<&Assignment> {
-        for assignment in &self.assignments {
-            if Self::assignment_match_link_name(&assignment.name, &name) {
-                return Some(assignment);
-            }
-        }
-        None
+
+    /// The earliest `next_sync` of the jobs that aren't paused.
+    pub fn next_sync(&self) -> Option<u64> {
+        self.jobs
+            .iter()
+            .filter(|job| !job.paused)
+            .map(|job| job.next_sync)
+            .min()
     }
 
-    fn mark_done(&mut self, link_name: String) {
-        if let Some(assignment) = self.get_assignment_from_link_name(link_name) {
-            let mut assignment = assignment.clone();
-            assignment.mark_done();
-            self.add_assignment(assignment);
-        }
+    fn due_jobs(&self) -> Vec<Job> {
+        self.jobs
+            .iter()
+            // a dry run of it may still be going, it gets its turn after
+            .filter(|job| job.sync_due() && !runner::is_running(&job.id))
+            .cloned()
+            .collect()
     }
 
-    pub async fn run_jobs(&mut self) {
+    /// Folds finished runs back in and queues the assignments that need pushing to the
+    /// calendar. Queued ones have `synced` set to false until the push is confirmed.
+    fn merge_results(&mut self, results: Vec<(Job, JobRun, Vec<Assignment>)>) {
         let mut assignments: Vec<Assignment> = Vec::new();
-        println!("Running jobs");
-        for job in &mut self.jobs {
-            if job.sync_due() {
-                println!("Syncing {}", job.name);
-                let new_assignments = job.sync().await.unwrap();
-                println!("Got {} assignments", new_assignments.len());
-                assignments.extend(new_assignments);
+        let mut outputs: Vec<(String, Vec<Assignment>)> = Vec::new();
+        for (ran, run, new_assignments) in results {
+            // the job may have been deleted while it ran
+            let Some(job) = self.jobs.iter_mut().find(|job| job.id == ran.id) else {
+                continue;
+            };
+            job.take_run_state(&ran);
+            if run.status == RunStatus::Success {
+                outputs.push((job.id.clone(), new_assignments.clone()));
             }
+            assignments.extend(new_assignments);
+            self.add_run(run);
         }
         let start_len = self.assignments.len();
-        let mut changed = self.get_changed_assignments(assignments);
-        for assignment in &mut self.assignments {
-            let needs_sync = !assignment.synced.unwrap_or(false);
-            if needs_sync {
-                println!("Needs sync: {}", assignment.name);
-            }
-            if needs_sync || assignment.done {
-                assignment.synced = Some(true);
-                changed.push(assignment.clone());
-            }
-        }
+        let changed = self.get_changed_assignments(assignments);
         for assignment in &changed {
             self.add_assignment(assignment.clone());
         }
         if start_len != self.assignments.len() {
             println!("Added {} assignments", self.assignments.len() - start_len);
         }
-        self.sync_to_google(changed).await;
-        self.save().unwrap();
-        if start_len != self.assignments.len() {
-            println!("Done syncing");
+        for (job_id, returned) in outputs {
+            self.track_output(&job_id, &returned);
         }
+        // new, changed and newly (un)completed assignments all have synced cleared
+        let mut to_delete: Vec<String> = Vec::new();
+        for i in 0..self.assignments.len() {
+            if self.assignments[i].synced.is_some() {
+                continue;
+            }
+            let assignment = &self.assignments[i];
+            println!("Needs sync: {}", assignment.name);
+            let job = assignment
+                .job_name
+                .as_ref()
+                .and_then(|job_name| self.get_job(job_name.clone()));
+            let push = assignment.job_name.is_some() && job.is_none_or(|job| job.sync_to_google);
+            let delete = assignment.removed_upstream.is_some()
+                && job.is_some_and(|job| job.removed_policy == RemovedPolicy::Delete);
+            if push {
+                let mut assignment = assignment.clone();
+                // done assignments are taken off the calendar
+                assignment.done |= delete;
+                crate::outbox::enqueue(self, &assignment, delete);
+                self.assignments[i].synced = Some(false);
+            } else {
+                if delete {
+                    to_delete.push(assignment.id.clone());
+                }
+                self.assignments[i].synced = Some(true);
+            }
+        }
+        self.assignments.retain(|a| !to_delete.contains(&a.id));
+    }
+}
+
+/// Runs the jobs at the same time, at most `max_concurrent_jobs` at once.
+async fn run_all(jobs: Vec<Job>) -> Vec<(Job, JobRun, Vec<Assignment>)> {
+    let limit = Arc::new(Semaphore::new(Config::get().max_concurrent_jobs.max(1)));
+    let handles: Vec<_> = jobs
+        .into_iter()
+        .map(|job| {
+            let limit = limit.clone();
+            let mut ran = job.clone();
+            let handle = tokio::spawn(async move {
+                let _permit = limit.acquire_owned().await.unwrap();
+                println!("Syncing {}", ran.name);
+                let (run, assignments) = ran.sync().await;
+                println!("Got {} assignments from {}", assignments.len(), ran.name);
+                (ran, run, assignments)
+            });
+            (job, handle)
+        })
+        .collect();
+    let mut results = Vec::new();
+    for (mut job, handle) in handles {
+        match handle.await {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                let message = match e.try_into_panic() {
+                    Ok(panic) => crate::health::panic_message(&*panic),
+                    Err(e) => e.to_string(),
+                };
+                let run = job.failed(message);
+                results.push((job, run, Vec::new()));
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(data: &mut ManagerData, scraped: Vec<Assignment>) {
+        for assignment in data.get_changed_assignments(scraped) {
+            data.add_assignment(assignment);
+        }
+    }
+
+    #[test]
+    fn local_state_survives_a_detail_change() {
+        let mut data = ManagerData::empty();
+        let due = now() + 24 * 60 * 60;
+        let scraped = Assignment::new("math".into(), due, "Essay".into(), "job".into());
+        merge(&mut data, vec![scraped.clone()]);
+        let id = data.assignments[0].id.clone();
+        data.mark_done(&id);
+        data.assignments[0].dismissed = true;
+
+        let mut rescraped = Assignment::new("math".into(), due, "Essay".into(), "job".into());
+        rescraped.description = Some("Now 2000 words".into());
+        rescraped.points = Some(20.0);
+        merge(&mut data, vec![rescraped]);
+
+        assert_eq!(data.assignments.len(), 1);
+        let assignment = &data.assignments[0];
+        assert_eq!(assignment.id, id);
+        assert_eq!(assignment.description.as_deref(), Some("Now 2000 words"));
+        assert_eq!(assignment.points, Some(20.0));
+        assert!(assignment.done);
+        assert!(assignment.completed_at.is_some());
+        assert!(assignment.dismissed);
+        assert_eq!(assignment.synced, None);
+    }
+
+    #[test]
+    fn completion_survives_a_due_date_change() {
+        let mut data = ManagerData::empty();
+        let due = now() + 24 * 60 * 60;
+        merge(
+            &mut data,
+            vec![Assignment::new(
+                "math".into(),
+                due,
+                "Quiz".into(),
+                "job".into(),
+            )],
+        );
+        let id = data.assignments[0].id.clone();
+        data.mark_done(&id);
+        let completed_at = data.assignments[0].completed_at;
+
+        for due in [due + 60 * 60, due + 2 * 60 * 60] {
+            merge(
+                &mut data,
+                vec![Assignment::new(
+                    "math".into(),
+                    due,
+                    "Quiz".into(),
+                    "job".into(),
+                )],
+            );
+            let assignment = &data.assignments[0];
+            assert_eq!(assignment.due, due);
+            assert!(assignment.done);
+            assert_eq!(assignment.completed_at, completed_at);
+        }
+
+        data.mark_undone(&id);
+        merge(
+            &mut data,
+            vec![Assignment::new(
+                "math".into(),
+                due,
+                "Quiz".into(),
+                "job".into(),
+            )],
+        );
+        assert!(!data.assignments[0].done);
+    }
+
+    #[test]
+    fn adding_a_job_again_keeps_its_working_dir() {
+        let mut data = ManagerData::empty();
+        let mut job = Job::new("job".into(), "/tmp".into(), false, 60);
+        job.command.program = "python3".into();
+        job.command.working_dir = Some("/srv/scrapers".into());
+        data.add_job(job.clone());
+
+        let mut again = Job::new("job".into(), "/tmp".into(), true, 30);
+        again.command.program = "node".into();
+        again.caldav = Some(CalDavConfig {
+            url: "https://dav.example.com/".into(),
+            ..Default::default()
+        });
+        data.add_job(again);
+
+        assert_eq!(data.jobs.len(), 1);
+        let updated = &data.jobs[0];
+        assert_eq!(updated.id, job.id);
+        assert_eq!(updated.command.program, "node");
+        assert_eq!(
+            updated.command.working_dir,
+            Some(PathBuf::from("/srv/scrapers"))
+        );
+        assert_eq!(updated.sync_interval, 30);
+        assert!(updated.caldav.is_some());
+    }
+
+    #[test]
+    fn editing_a_job_keeps_its_retry_unless_the_schedule_changed() {
+        let mut job = Job::new("job".into(), "/tmp".into(), false, 60);
+        job.last_sync = now();
+        // waiting out a backoff
+        job.next_sync = job.last_sync + 5;
+
+        let mut edited = job.clone();
+        edited.timeout = 10;
+        job.update_from(edited.clone());
+        assert_eq!(job.next_sync, job.last_sync + 5);
+
+        edited.sync_interval = 120;
+        job.update_from(edited);
+        assert_eq!(job.next_sync, job.last_sync + 120);
+    }
+
+    #[test]
+    fn redacted_data_has_no_caldav_password() {
+        let mut data = ManagerData::empty();
+        let mut job = Job::new("job".into(), "/tmp".into(), false, 60);
+        job.caldav = Some(CalDavConfig {
+            url: "https://dav.example.com/".into(),
+            username: "alice".into(),
+            password: Some("hunter2".into()),
+            ..Default::default()
+        });
+        data.jobs.push(job);
+
+        let json = serde_json::to_string(&data.redacted()).unwrap();
+        assert!(json.contains("alice"));
+        assert!(!json.contains("hunter2"));
     }
 }
@@ -10,6 +10,12 @@ impl Headers {
     pub fn contains(&self, name: String) -> bool {
         self.headers.iter().any(|h| h.name == name)
     }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.name == name)
+            .map(|h| h.value.as_str())
+    }
 }
 #[rocket::async_trait]
 impl<'r> rocket::request::FromRequest<'r> for Headers {
@@ -28,3 +34,10 @@ impl<'r> rocket::request::FromRequest<'r> for Headers {
         rocket::request::Outcome::Success(Headers { headers })
     }
 }
+
+/// A rendered page, or a redirect to where the page lives now.
+#[derive(rocket::Responder)]
+pub enum PageResponse {
+    Page(rocket_dyn_templates::Template),
+    Redirect(rocket::response::Redirect),
+}
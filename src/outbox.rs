@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::calendar::{self, CalendarBackend, Event, RemoteEvent};
+use crate::config::Config;
+use crate::manager::{backoff, new_id, now, Assignment, Manager, ManagerData};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxOp {
+    Create,
+    Update,
+    Delete,
+}
+
+impl OutboxOp {
+    fn for_assignment(assignment: &Assignment, backend: &dyn CalendarBackend) -> Self {
+        if assignment.done {
+            Self::Delete
+        } else if assignment.remote_events.contains_key(backend.name()) {
+            Self::Update
+        } else {
+            Self::Create
+        }
+    }
+}
+
+/// A change waiting to be pushed to the calendar. Entries are saved with everything else, so
+/// they survive restarts, and only go away once the calendar has confirmed them.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct OutboxEntry {
+    pub id: String,
+    pub assignment_id: String,
+    pub op: OutboxOp,
+    /// The assignment as it should end up on the calendar, in case it is gone locally by the
+    /// time the entry is pushed.
+    pub assignment: Assignment,
+    /// Delete the assignment once it is off the calendar.
+    pub remove_after: bool,
+    pub queued_at: u64,
+    /// Failed attempts so far.
+    pub attempts: u32,
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+}
+
+/// The calendar `assignment`'s job sends it to.
+fn backend_for(data: &ManagerData, assignment: &Assignment) -> Arc<dyn CalendarBackend> {
+    let job = data
+        .jobs
+        .iter()
+        .find(|job| Some(&job.name) == assignment.job_name.as_ref());
+    calendar::backend_for(job)
+}
+
+/// Queues `assignment` to be pushed. An assignment only ever has one entry, so a newer change
+/// replaces whatever was still waiting.
+pub fn enqueue(data: &mut ManagerData, assignment: &Assignment, remove_after: bool) {
+    let backend = backend_for(data, assignment);
+    let now = now();
+    data.outbox
+        .retain(|entry| entry.assignment_id != assignment.id);
+    data.outbox.push(OutboxEntry {
+        id: new_id(),
+        assignment_id: assignment.id.clone(),
+        op: OutboxOp::for_assignment(assignment, backend.as_ref()),
+        assignment: assignment.clone(),
+        remove_after,
+        queued_at: now,
+        attempts: 0,
+        next_attempt: now,
+        last_error: None,
+    });
+}
+
+pub fn get_entry<'a>(data: &'a ManagerData, assignment_id: &str) -> Option<&'a OutboxEntry> {
+    data.outbox
+        .iter()
+        .find(|entry| entry.assignment_id == assignment_id)
+}
+
+/// When the next entry is due.
+pub fn next_attempt(data: &ManagerData) -> Option<u64> {
+    data.outbox.iter().map(|entry| entry.next_attempt).min()
+}
+
+/// Pushes the entries that are due, outside the save lock, and then records how it went.
+/// Returns an error when any of them failed.
+pub async fn drain() -> Result<(), std::io::Error> {
+    let now = now();
+    let data = Manager::read_no_save();
+    // entries for assignments deleted since they were queued would only put them back, unless
+    // they take them off the calendar
+    let (due, stale): (Vec<&OutboxEntry>, Vec<&OutboxEntry>) = data
+        .outbox
+        .iter()
+        .filter(|entry| entry.next_attempt <= now)
+        .partition(|entry| {
+            entry.assignment.done || data.get_assignment(&entry.assignment_id).is_some()
+        });
+    let stale: Vec<String> = stale.into_iter().map(|entry| entry.id.clone()).collect();
+    let due: Vec<(OutboxEntry, Arc<dyn CalendarBackend>)> = due
+        .into_iter()
+        .cloned()
+        .map(|mut entry| {
+            // an earlier push may have made the event since this was queued
+            if let Some(current) = data.get_assignment(&entry.assignment_id) {
+                entry.assignment.remote_events = current.remote_events.clone();
+            }
+            let backend = backend_for(&data, &entry.assignment);
+            entry.op = OutboxOp::for_assignment(&entry.assignment, backend.as_ref());
+            (entry, backend)
+        })
+        .collect();
+    if due.is_empty() && stale.is_empty() {
+        return Ok(());
+    }
+
+    // listed at most once per calendar
+    let mut unclaimed: HashMap<String, Option<Vec<Event>>> = HashMap::new();
+    let mut results: Vec<(OutboxEntry, String, Result<Option<RemoteEvent>, String>)> = Vec::new();
+    for (entry, backend) in due {
+        let name = backend.name().to_string();
+        let unclaimed = unclaimed.entry(name.clone()).or_default();
+        let result = calendar::push(backend.as_ref(), &entry.assignment, unclaimed)
+            .await
+            .map_err(|e| e.to_string());
+        results.push((entry, name, result));
+    }
+
+    let mut manager = Manager::from_save_file();
+    manager
+        .data
+        .outbox
+        .retain(|entry| !stale.contains(&entry.id));
+    let failed = apply(&mut manager.data, results);
+    manager.save()?;
+    if failed == 0 {
+        return Ok(());
+    }
+    Err(std::io::Error::other(format!(
+        "Could not sync {} assignments to the calendar",
+        failed
+    )))
+}
+
+/// Records pushed events and finishes or reschedules the entries. Entries that were replaced
+/// while they were being pushed are left for the next drain. Returns how many failed.
+fn apply(
+    data: &mut ManagerData,
+    results: Vec<(OutboxEntry, String, Result<Option<RemoteEvent>, String>)>,
+) -> usize {
+    let config = Config::get();
+    let mut failed = 0;
+    for (pushed, backend, result) in results {
+        let current = data.outbox.iter().position(|entry| entry.id == pushed.id);
+        let assignment = data
+            .assignments
+            .iter_mut()
+            .find(|a| a.id == pushed.assignment_id);
+        match result {
+            Ok(event) => {
+                println!("Pushed {} to the calendar", pushed.assignment.name);
+                let Some(assignment) = assignment else {
+                    if let Some(i) = current {
+                        data.outbox.remove(i);
+                    }
+                    // deleted while it was being pushed, so what the push made has to go too
+                    if let Some(event) = event {
+                        let mut gone = get_entry(data, &pushed.assignment_id)
+                            .map(|entry| entry.assignment.clone())
+                            .unwrap_or(pushed.assignment);
+                        gone.done = true;
+                        gone.remote_events.insert(backend, event);
+                        enqueue(data, &gone, false);
+                    }
+                    continue;
+                };
+                match event {
+                    Some(event) => assignment.remote_events.insert(backend, event),
+                    None => assignment.remote_events.remove(&backend),
+                };
+                let Some(i) = current else {
+                    continue;
+                };
+                data.outbox.remove(i);
+                assignment.synced = Some(true);
+                if pushed.remove_after {
+                    let id = pushed.assignment_id.clone();
+                    data.assignments.retain(|a| a.id != id);
+                }
+            }
+            Err(error) => {
+                failed += 1;
+                let Some(i) = current else {
+                    continue;
+                };
+                let entry = &mut data.outbox[i];
+                entry.attempts += 1;
+                let delay = backoff(
+                    config.sync_backoff_base,
+                    config.sync_backoff_cap,
+                    10,
+                    entry.attempts,
+                );
+                println!(
+                    "Could not push {} to the calendar, retrying in {}s: {}",
+                    entry.assignment.name, delay, error
+                );
+                entry.next_attempt = now() + delay;
+                entry.last_error = Some(error);
+            }
+        }
+    }
+    failed
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::manager::{ScriptAssignment, ScriptOutput};
+
+/// How many `log` messages a running job keeps around for the job view.
+const LOG_LINES: usize = 10;
+
+/// One line of a job's output in the streaming format, like
+/// `{"type": "progress", "current": 3, "total": 10}`. A job that prints any of these has to end
+/// with `done`, so a crash halfway through isn't mistaken for a short list of assignments.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Assignment(ScriptAssignment),
+    Log {
+        #[serde(default)]
+        level: Option<String>,
+        message: String,
+    },
+    Progress {
+        #[serde(default)]
+        current: Option<u64>,
+        #[serde(default)]
+        total: Option<u64>,
+        #[serde(default)]
+        message: Option<String>,
+    },
+    /// Fails the run, even if the script exits cleanly.
+    Error {
+        message: String,
+    },
+    Done,
+}
+
+/// What a running job has reported so far.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct Progress {
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+    pub message: Option<String>,
+    /// Assignments received so far.
+    pub assignments: usize,
+    /// The last few `log` and `error` messages, oldest first.
+    #[schema(value_type = Vec<String>)]
+    pub log: VecDeque<String>,
+}
+
+impl Progress {
+    fn log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+}
+
+/// Parses a job's stdout as it arrives. Lines that aren't messages are ignored, so stray prints
+/// don't break anything.
+#[derive(Default)]
+pub struct Stream {
+    assignments: Vec<ScriptAssignment>,
+    error: Option<String>,
+    done: bool,
+    /// Whether any line was a message. Output without any is parsed the old way.
+    streaming: bool,
+}
+
+impl Stream {
+    pub fn feed(&mut self, line: &[u8], progress: &Mutex<Progress>) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if !line.starts_with('{') {
+            return;
+        }
+        let Ok(message) = serde_json::from_str::<Message>(line) else {
+            return;
+        };
+        self.streaming = true;
+        let mut progress = progress.lock().unwrap();
+        match message {
+            Message::Assignment(assignment) => {
+                self.assignments.push(assignment);
+                progress.assignments = self.assignments.len();
+            }
+            Message::Log { level, message } => progress.log(match level {
+                Some(level) => format!("[{}] {}", level, message),
+                None => message,
+            }),
+            Message::Progress {
+                current,
+                total,
+                message,
+            } => {
+                progress.current = current;
+                progress.total = total;
+                progress.message = message;
+            }
+            Message::Error { message } => {
+                progress.log(format!("[error] {}", message));
+                self.error.get_or_insert(message);
+            }
+            Message::Done => self.done = true,
+        }
+    }
+
+    /// The assignments the job returned. `stdout` is the whole output, for jobs that print a
+    /// single JSON document instead of messages.
+    pub fn finish(self, stdout: &[u8]) -> Result<Vec<ScriptAssignment>, String> {
+        if !self.streaming {
+            return ScriptOutput::parse(stdout)
+                .map_err(|e| format!("Could not parse output: {}", e));
+        }
+        if let Some(error) = self.error {
+            return Err(format!("Script reported an error: {}", error));
+        }
+        if !self.done {
+            return Err("Output ended without a done message".to_string());
+        }
+        Ok(self.assignments)
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+use crate::manager::JobCommand;
+use crate::protocol::{Progress, Stream};
+
+pub enum RunOutcome {
+    /// The stream has already seen all of stdout.
+    Finished(std::process::Output, Stream),
+    TimedOut,
+    Cancelled,
+}
+
+/// A job with a process running right now.
+#[derive(Default)]
+struct Running {
+    cancel: Notify,
+    progress: Mutex<Progress>,
+}
+
+/// Running jobs by job id. This lives outside `ManagerData` so a run can be cancelled or
+/// watched without waiting for the save lock the scheduler is holding.
+static RUNNING: LazyLock<Mutex<HashMap<String, Arc<Running>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Asks a running job to stop. Returns false if it isn't running.
+pub fn cancel(id: &str) -> bool {
+    match RUNNING.lock().unwrap().get(id) {
+        Some(running) => {
+            // notify_one keeps the permit if the runner isn't waiting yet
+            running.cancel.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Set once the server is shutting down. Jobs that haven't started yet are cancelled right away.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Cancels every running job and any that would start after this.
+pub fn stop_all() {
+    STOPPED.store(true, Ordering::SeqCst);
+    for running in RUNNING.lock().unwrap().values() {
+        running.cancel.notify_one();
+    }
+}
+
+pub fn is_running(id: &str) -> bool {
+    RUNNING.lock().unwrap().contains_key(id)
+}
+
+/// What the job has reported so far, or `None` if it isn't running.
+pub fn progress(id: &str) -> Option<Progress> {
+    let running = RUNNING.lock().unwrap().get(id)?.clone();
+    let progress = running.progress.lock().unwrap().clone();
+    Some(progress)
+}
+
+/// Removes the job from `RUNNING` however the run ends.
+struct Registration(String);
+
+impl Registration {
+    /// Fails if the job is running already, a dry run and a real run of it would take the
+    /// registration away from each other.
+    fn new(id: &str) -> Result<(Self, Arc<Running>), std::io::Error> {
+        let running = Arc::new(Running::default());
+        match RUNNING.lock().unwrap().entry(id.to_string()) {
+            Entry::Occupied(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "The job is already running",
+                ))
+            }
+            Entry::Vacant(entry) => entry.insert(running.clone()),
+        };
+        Ok((Self(id.to_string()), running))
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Kills the process and everything it started. The child leads its own process group, so
+/// scrapers that spawn a browser don't leave it behind. Takes the pid from before the child was
+/// waited on, since `Child::id` is gone by then.
+fn kill_tree(pid: Option<u32>, child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+        return;
+    }
+    _ = child.start_kill();
+}
+
+/// Runs `command` for the job `id`, killing it after `timeout` or when `cancel` is called.
+pub async fn run(
+    id: &str,
+    command: &JobCommand,
+    dir: &Path,
+    timeout: Option<Duration>,
+) -> Result<RunOutcome, std::io::Error> {
+    let mut process = std::process::Command::new(&command.program);
+    process
+        .args(&command.args)
+        .current_dir(command.working_dir.as_deref().unwrap_or(dir))
+        .envs(&command.env)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .stdin(if command.stdin.is_some() {
+            std::process::Stdio::piped()
+        } else {
+            std::process::Stdio::null()
+        });
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut process, 0);
+    let mut process = Command::from(process);
+    process.kill_on_drop(true);
+
+    let (_registration, running) = Registration::new(id)?;
+    // checked after registering so stop_all can't slip in between
+    if STOPPED.load(Ordering::SeqCst) {
+        return Ok(RunOutcome::Cancelled);
+    }
+    let mut child = process.spawn()?;
+    let pid = child.id();
+    let stdin = child.stdin.take();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let progress = running.clone();
+    let mut stdout = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let mut stream = Stream::default();
+        loop {
+            let start = buf.len();
+            if stdout.read_until(b'\n', &mut buf).await? == 0 {
+                break;
+            }
+            stream.feed(&buf[start..], &progress.progress);
+        }
+        Ok::<_, std::io::Error>((buf, stream))
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let mut stderr = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).await.map(|_| buf)
+    });
+
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    // everything up to having all the output, so a script that never reads its input or leaves
+    // something holding the pipes open still times out and can be cancelled
+    let finish = async {
+        if let (Some(mut pipe), Some(input)) = (stdin, &command.stdin) {
+            match pipe.write_all(input.as_bytes()).await {
+                // a script that exits without reading all of its input still ran, its exit
+                // status and output say how
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                written => written?,
+            }
+            // closing the pipe lets the script see the end of its input
+            drop(pipe);
+        }
+        let status = child.wait().await?;
+        // whatever it left running in the background would keep the pipes open
+        kill_tree(pid, &mut child);
+        let (stdout, stream) = (&mut stdout).await.map_err(std::io::Error::other)??;
+        let stderr = (&mut stderr).await.map_err(std::io::Error::other)??;
+        Ok::<_, std::io::Error>(RunOutcome::Finished(
+            std::process::Output {
+                status,
+                stdout,
+                stderr,
+            },
+            stream,
+        ))
+    };
+    let stopped = tokio::select! {
+        finished = finish => match finished {
+            Ok(finished) => return Ok(finished),
+            Err(e) => Err(e),
+        },
+        _ = deadline => Ok(RunOutcome::TimedOut),
+        _ = running.cancel.notified() => Ok(RunOutcome::Cancelled),
+    };
+    kill_tree(pid, &mut child);
+    _ = child.wait().await;
+    stdout.abort();
+    stderr.abort();
+    stopped
+}
@@ -0,0 +1,541 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// How far ahead to look for a time the schedule allows before giving up on it. Dates and
+/// weekdays repeat every 400 years, so a schedule that doesn't run within that never runs, while
+/// one like `0 0 29 2 *` can be years away.
+const SEARCH_LIMIT: u64 = 146097 * 24 * 60 * 60;
+
+/// When a job may run, on top of `sync_interval`. All times are in the server's local time.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema, FromForm)]
+#[serde(default)]
+pub struct Schedule {
+    /// Five field cron expression (`minute hour day month weekday`) or one of `@hourly`, `@daily`,
+    /// `@weekly`, `@monthly` and `@yearly`. Replaces `sync_interval` when set.
+    pub cron: Option<String>,
+    /// Time of day runs may start from, as `HH:MM`.
+    pub window_start: Option<String>,
+    /// Time of day runs have to start before, as `HH:MM`. A window that ends before it starts
+    /// wraps past midnight.
+    pub window_end: Option<String>,
+    /// Days runs may start on, 0 being Sunday. Empty allows every day.
+    pub weekdays: Vec<u8>,
+}
+
+impl Schedule {
+    /// Empty form fields come in as empty strings rather than missing.
+    pub fn normalize(mut self) -> Self {
+        for field in [&mut self.cron, &mut self.window_start, &mut self.window_end] {
+            *field = field
+                .take()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty());
+        }
+        self.weekdays.sort();
+        self.weekdays.dedup();
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(cron) = &self.cron {
+            Cron::parse(cron)?;
+        }
+        if self.window_start.is_some() != self.window_end.is_some() {
+            return Err("A window needs both a start and an end".to_string());
+        }
+        for time in [&self.window_start, &self.window_end].into_iter().flatten() {
+            parse_time_of_day(time)?;
+        }
+        if let Some(day) = self.weekdays.iter().find(|day| **day > 6) {
+            return Err(format!("{} is not a weekday, use 0 (Sunday) to 6", day));
+        }
+        if self
+            .find(crate::manager::now(), self.cron().as_ref())
+            .is_none()
+        {
+            return Err("This schedule never runs".to_string());
+        }
+        Ok(())
+    }
+
+    fn cron(&self) -> Option<Cron> {
+        let cron = self.cron.as_ref()?;
+        match Cron::parse(cron) {
+            Ok(cron) => Some(cron),
+            Err(e) => {
+                println!("Ignoring invalid cron expression {:?}: {}", cron, e);
+                None
+            }
+        }
+    }
+
+    fn window(&self) -> Option<(u32, u32)> {
+        let start = parse_time_of_day(self.window_start.as_ref()?).ok()?;
+        let end = parse_time_of_day(self.window_end.as_ref()?).ok()?;
+        Some((start, end))
+    }
+
+    /// When a job that last started at `last` should run next.
+    pub fn next_run(&self, last: u64, interval: u64) -> u64 {
+        let found = match self.cron() {
+            // cron fires on whole minutes, after the one the last run started in
+            Some(cron) => self.find(last - last % 60 + 60, Some(&cron)),
+            None => self.find(last + interval, None),
+        };
+        found.unwrap_or(last + interval)
+    }
+
+    /// Moves `time` forward into the window and onto an allowed day. Used for retries, which
+    /// don't wait for the next cron time.
+    pub fn allowed_from(&self, time: u64) -> u64 {
+        self.find(time, None).unwrap_or(time)
+    }
+
+    /// First time at or after `from` that the window, the weekdays and `cron` all allow.
+    fn find(&self, from: u64, cron: Option<&Cron>) -> Option<u64> {
+        let window = self.window();
+        let mut time = from;
+        while time < from + SEARCH_LIMIT {
+            let local = LocalTime::at(time);
+            let day_ok = (self.weekdays.is_empty() || self.weekdays.contains(&local.weekday))
+                && cron.is_none_or(|cron| cron.matches_day(&local));
+            if !day_ok {
+                time = local.next_day(time);
+                continue;
+            }
+            if !cron.is_none_or(|cron| cron.matches_hour(&local)) {
+                time = local.next_hour(time);
+                continue;
+            }
+            let minute = local.hour * 60 + local.minute;
+            if let Some((start, end)) = window {
+                let in_window = if start <= end {
+                    start <= minute && minute < end
+                } else {
+                    minute >= start || minute < end
+                };
+                if !in_window {
+                    // straight to the start of the window, minute by minute takes too long
+                    time = if minute < start {
+                        time - local.second as u64 + (start - minute) as u64 * 60
+                    } else {
+                        local.next_day(time)
+                    };
+                    continue;
+                }
+            }
+            if !cron.is_none_or(|cron| cron.matches_minute(&local)) {
+                time = local.next_minute(time);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+}
+
+/// The local date `time` falls on, as `YYYY-MM-DD`.
+pub fn local_date(time: u64) -> String {
+    let local = LocalTime::at(time);
+    format!("{:04}-{:02}-{:02}", local.year, local.month, local.day)
+}
+
+fn parse_time_of_day(time: &str) -> Result<u32, String> {
+    let invalid = || format!("{:?} is not a time of day, use HH:MM", time);
+    let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok(hour * 60 + minute)
+}
+
+/// The parts of a timestamp the schedule cares about, in local time.
+struct LocalTime {
+    second: u32,
+    minute: u32,
+    hour: u32,
+    /// 1 to 31.
+    day: u32,
+    /// 1 to 12.
+    month: u32,
+    year: i32,
+    /// 0 is Sunday.
+    weekday: u8,
+}
+
+impl LocalTime {
+    #[cfg(unix)]
+    fn at(time: u64) -> Self {
+        let time = time as libc::time_t;
+        // SAFETY: localtime_r only writes to the tm we pass it
+        let tm = unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&time, &mut tm);
+            tm
+        };
+        Self {
+            second: tm.tm_sec as u32,
+            minute: tm.tm_min as u32,
+            hour: tm.tm_hour as u32,
+            day: tm.tm_mday as u32,
+            month: tm.tm_mon as u32 + 1,
+            year: tm.tm_year + 1900,
+            weekday: tm.tm_wday as u8,
+        }
+    }
+
+    /// Without a way to read the local time zone, schedules are in UTC.
+    #[cfg(not(unix))]
+    fn at(time: u64) -> Self {
+        let days = time / 86400;
+        let seconds = time % 86400;
+        // days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+        let z = days as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            second: (seconds % 60) as u32,
+            minute: (seconds / 60 % 60) as u32,
+            hour: (seconds / 3600) as u32,
+            day: (doy - (153 * mp + 2) / 5 + 1) as u32,
+            month: month as u32,
+            year: (yoe + era * 400 + (month <= 2) as i64) as i32,
+            weekday: ((days + 4) % 7) as u8,
+        }
+    }
+
+    fn next_minute(&self, time: u64) -> u64 {
+        time - self.second as u64 + 60
+    }
+
+    fn next_hour(&self, time: u64) -> u64 {
+        time - self.second as u64 - self.minute as u64 * 60 + 60 * 60
+    }
+
+    /// Midnight, give or take a daylight saving change, which the next check catches.
+    fn next_day(&self, time: u64) -> u64 {
+        time - self.second as u64 - self.minute as u64 * 60 - self.hour as u64 * 60 * 60
+            + 24 * 60 * 60
+    }
+}
+
+/// A parsed cron expression. Each field is a bit set of the values it allows.
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Like cron, a job with both days and weekdays restricted runs when either matches.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekday_field] = fields[..] else {
+            return Err(format!(
+                "Expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        let mut weekdays = parse_field(weekday_field, 0, 7, WEEKDAYS)?;
+        // 7 is also Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59, &[])?,
+            hours: parse_field(hours, 0, 23, &[])?,
+            days: parse_field(days, 1, 31, &[])?,
+            months: parse_field(months, 1, 12, MONTHS)?,
+            weekdays,
+            any_day: days.starts_with('*'),
+            any_weekday: weekday_field.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, time: &LocalTime) -> bool {
+        if self.months & (1 << time.month) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << time.day) != 0;
+        let weekday = self.weekdays & (1 << time.weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    fn matches_hour(&self, time: &LocalTime) -> bool {
+        self.hours & (1 << time.hour) != 0
+    }
+
+    fn matches_minute(&self, time: &LocalTime) -> bool {
+        self.minutes & (1 << time.minute) != 0
+    }
+}
+
+/// Parses one comma separated cron field like `1-5,10-20/2` into a bit set. `names` are accepted
+/// in place of numbers, counting from `min`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |v: &str| -> Result<u32, String> {
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(v)) {
+            Some(i) => i as u32 + min,
+            None => v
+                .parse()
+                .map_err(|_| format!("{:?} is not a valid cron value", v))?,
+        };
+        if value < min || value > max {
+            return Err(format!("{} is out of range {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("{:?} is not a valid step", step))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/10` means every 10 starting at 5
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("{:?} is an empty range", range));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The timestamp of a local time, the way `LocalTime` reads it.
+    #[cfg(unix)]
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        // SAFETY: mktime only reads and normalizes the tm we pass it
+        unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            tm.tm_year = year - 1900;
+            tm.tm_mon = month as i32 - 1;
+            tm.tm_mday = day as i32;
+            tm.tm_hour = hour as i32;
+            tm.tm_min = minute as i32;
+            tm.tm_isdst = -1;
+            libc::mktime(&mut tm) as u64
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        use rocket::time::{Date, Month, PrimitiveDateTime, Time};
+        let date = Date::from_calendar_date(year, Month::try_from(month as u8).unwrap(), day as u8);
+        let time = Time::from_hms(hour as u8, minute as u8, 0).unwrap();
+        PrimitiveDateTime::new(date.unwrap(), time)
+            .assume_utc()
+            .unix_timestamp() as u64
+    }
+
+    fn cron(expression: &str) -> Schedule {
+        Schedule {
+            cron: Some(expression.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn window(start: &str, end: &str) -> Schedule {
+        Schedule {
+            window_start: Some(start.to_string()),
+            window_end: Some(end.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn bits(values: &[u32]) -> u64 {
+        values.iter().fold(0, |bits, v| bits | 1 << v)
+    }
+
+    #[test]
+    fn local_time_reads_the_date() {
+        let time = LocalTime::at(local(2024, 2, 29, 13, 45));
+        assert_eq!((time.year, time.month, time.day), (2024, 2, 29));
+        assert_eq!((time.hour, time.minute, time.second), (13, 45, 0));
+        // a Thursday
+        assert_eq!(time.weekday, 4);
+        assert_eq!(local_date(local(2026, 12, 31, 23, 59)), "2026-12-31");
+    }
+
+    #[test]
+    fn cron_fields_take_ranges_steps_and_names() {
+        let cron = Cron::parse("1-5,10-20/5 */6 5/10 jan-MAR mon-fri").unwrap();
+        assert_eq!(cron.minutes, bits(&[1, 2, 3, 4, 5, 10, 15, 20]));
+        assert_eq!(cron.hours, bits(&[0, 6, 12, 18]));
+        assert_eq!(cron.days, bits(&[5, 15, 25]));
+        assert_eq!(cron.months, bits(&[1, 2, 3]));
+        assert_eq!(cron.weekdays, bits(&[1, 2, 3, 4, 5]));
+
+        // 7 is Sunday too
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays & 1, 1);
+        let daily = Cron::parse("@daily").unwrap();
+        assert_eq!((daily.minutes, daily.hours), (1, 1));
+
+        for invalid in [
+            "60 * * * *",
+            "* * * *",
+            "* * * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(Cron::parse(invalid).is_err(), "{} parsed", invalid);
+        }
+        assert!(Cron::parse("0 0 * foo *").is_err());
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_either_match() {
+        // Thursday the 1st
+        let from = local(2026, 10, 1, 0, 0);
+        // the 13th or any Friday
+        assert_eq!(
+            cron("0 12 13 * 5").next_run(from, 60),
+            local(2026, 10, 2, 12, 0)
+        );
+        // just the 13th
+        assert_eq!(
+            cron("0 12 13 * *").next_run(from, 60),
+            local(2026, 10, 13, 12, 0)
+        );
+        // just Fridays
+        assert_eq!(
+            cron("0 12 * * fri").next_run(from, 60),
+            local(2026, 10, 2, 12, 0)
+        );
+    }
+
+    #[test]
+    fn cron_runs_after_the_minute_of_the_last_run() {
+        let last = local(2026, 10, 1, 10, 0) + 30;
+        assert_eq!(
+            cron("*/15 * * * *").next_run(last, 60),
+            local(2026, 10, 1, 10, 15)
+        );
+        assert_eq!(
+            cron("* * * * *").next_run(last, 60),
+            local(2026, 10, 1, 10, 1)
+        );
+    }
+
+    #[test]
+    fn windows_can_wrap_past_midnight() {
+        let night = window("22:00", "06:00");
+        assert_eq!(
+            night.allowed_from(local(2026, 10, 1, 12, 0)),
+            local(2026, 10, 1, 22, 0)
+        );
+        assert_eq!(
+            night.allowed_from(local(2026, 10, 1, 3, 0)),
+            local(2026, 10, 1, 3, 0)
+        );
+        assert_eq!(
+            night.allowed_from(local(2026, 10, 1, 6, 0)),
+            local(2026, 10, 1, 22, 0)
+        );
+
+        let day = window("09:00", "17:00");
+        assert_eq!(
+            day.allowed_from(local(2026, 10, 1, 18, 0)),
+            local(2026, 10, 2, 9, 0)
+        );
+        assert_eq!(
+            day.allowed_from(local(2026, 10, 1, 16, 59)),
+            local(2026, 10, 1, 16, 59)
+        );
+    }
+
+    #[test]
+    fn weekdays_skip_to_the_next_allowed_day() {
+        // Monday and Wednesday, from a Thursday
+        let schedule = Schedule {
+            weekdays: vec![1, 3],
+            ..window("08:00", "09:00")
+        };
+        assert_eq!(
+            schedule.allowed_from(local(2026, 10, 1, 10, 0)),
+            local(2026, 10, 5, 8, 0)
+        );
+        // no weekdays allows every day
+        assert_eq!(
+            window("08:00", "09:00").allowed_from(local(2026, 10, 1, 10, 0)),
+            local(2026, 10, 2, 8, 0)
+        );
+        let invalid = Schedule {
+            weekdays: vec![7],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn leap_days_are_found_years_ahead() {
+        let leap_day = cron("0 0 29 2 *");
+        assert_eq!(leap_day.validate(), Ok(()));
+        assert_eq!(
+            leap_day.next_run(local(2026, 10, 1, 0, 0), 60),
+            local(2028, 2, 29, 0, 0)
+        );
+        // 2100 isn't a leap year
+        assert_eq!(
+            leap_day.next_run(local(2097, 1, 1, 0, 0), 60),
+            local(2104, 2, 29, 0, 0)
+        );
+    }
+
+    #[test]
+    fn schedules_that_never_run_are_rejected() {
+        assert!(cron("0 0 30 2 *").validate().is_err());
+        assert!(cron("0 0 31 apr *").validate().is_err());
+        assert!(window("10:00", "10:00").validate().is_err());
+        let outside = Schedule {
+            cron: Some("30 * * * *".to_string()),
+            ..window("10:00", "10:10")
+        };
+        assert!(outside.validate().is_err());
+        assert!(window("10:00", "").normalize().validate().is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Orbit, Rocket};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::health;
+use crate::manager::{self, now};
+use crate::outbox;
+
+/// Longest the scheduler sleeps, even with nothing due, so past due assignments get archived and
+/// failed calendar pushes get retried.
+const MAX_SLEEP: u64 = 60;
+
+static WAKE: LazyLock<Notify> = LazyLock::new(Notify::new);
+static STOP: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// Makes the scheduler look at the jobs again now instead of when it planned to. Call it after
+/// anything that changes when a job should run or what has to go to the calendar.
+pub fn wake() {
+    WAKE.notify_one();
+}
+
+/// Runs due jobs in the background for as long as Rocket is up.
+#[derive(Default)]
+pub struct Scheduler {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[rocket::async_trait]
+impl Fairing for Scheduler {
+    fn info(&self) -> Info {
+        Info {
+            name: "Scheduler",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        *self.task.lock().unwrap() = Some(tokio::spawn(run()));
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        println!("Stopping the scheduler...");
+        STOP.notify_one();
+        // running jobs are cancelled and recorded as such by the pass they are in
+        crate::runner::stop_all();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            _ = task.await;
+        }
+        println!("Scheduler stopped");
+    }
+}
+
+/// When the next job or calendar push is due.
+fn next_due() -> Option<u64> {
+    let data = manager::Manager::read_no_save();
+    data.next_sync()
+        .into_iter()
+        .chain(outbox::next_attempt(&data))
+        .min()
+}
+
+async fn run() {
+    loop {
+        // a panic in the pass only takes down its own task. Reading the save data can panic too,
+        // so working out the next wake up happens in there as well.
+        let pass = tokio::spawn(async {
+            let result = manager::Manager::run_due_jobs().await;
+            (result, next_due())
+        });
+        let next = match pass.await {
+            Ok((result, next)) => {
+                match result {
+                    Ok(()) => health::scheduler_ok(),
+                    Err(e) => health::scheduler_failed(e.to_string()),
+                }
+                next
+            }
+            Err(e) => {
+                health::scheduler_failed(match e.try_into_panic() {
+                    Ok(panic) => health::panic_message(&*panic),
+                    Err(e) => e.to_string(),
+                });
+                None
+            }
+        };
+
+        let sleep = match next {
+            // sync_due wants next_sync to be in the past, not this second
+            Some(next) => next.saturating_sub(now()) + 1,
+            None => MAX_SLEEP,
+        };
+        let sleep = Duration::from_secs(sleep.min(MAX_SLEEP));
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = WAKE.notified() => println!("Scheduler woken up"),
+            _ = STOP.notified() => return,
+        }
+    }
+}
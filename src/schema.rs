@@ -0,0 +1,84 @@
+use serde_json::{json, Value};
+
+use crate::manager::{new_id, JobCommand, ManagerData};
+
+/// Version of the save data this binary writes. Bump it and add a step to `MIGRATIONS` whenever a
+/// change to `ManagerData` can't be read from older saves as is.
+pub const SCHEMA_VERSION: u64 = 3;
+
+/// `MIGRATIONS[n]` upgrades a document from version `n` to `n + 1`.
+const MIGRATIONS: &[fn(&mut Value)] = &[v0_to_v1, v1_to_v2, v2_to_v3];
+
+/// Saves from before `schema_version` existed. Old assignments can be missing `job_name`,
+/// `synced`, `link_name` and `done`.
+fn v0_to_v1(doc: &mut Value) {
+    if let Some(assignments) = doc["assignments"].as_array_mut() {
+        for assignment in assignments {
+            let name = assignment["name"].as_str().unwrap_or_default().to_string();
+            let Some(assignment) = assignment.as_object_mut() else {
+                continue;
+            };
+            assignment.entry("job_name").or_insert(Value::Null);
+            assignment.entry("synced").or_insert(Value::Null);
+            assignment.entry("done").or_insert(json!(false));
+            assignment
+                .entry("link_name")
+                .or_insert(json!(crate::manager::Assignment::get_link_name(name)));
+        }
+    }
+}
+
+/// Jobs and assignments get a stable `id` instead of being looked up by name.
+fn v1_to_v2(doc: &mut Value) {
+    for list in ["jobs", "assignments"] {
+        if let Some(items) = doc[list].as_array_mut() {
+            for item in items {
+                if let Some(item) = item.as_object_mut() {
+                    item.entry("id").or_insert_with(|| json!(new_id()));
+                }
+            }
+        }
+    }
+}
+
+/// Jobs carry the command they run. Everything before this ran `node index.js` in the job path.
+fn v2_to_v3(doc: &mut Value) {
+    if let Some(jobs) = doc["jobs"].as_array_mut() {
+        for job in jobs {
+            if let Some(job) = job.as_object_mut() {
+                job.entry("command")
+                    .or_insert_with(|| json!(JobCommand::default()));
+            }
+        }
+    }
+}
+
+pub fn get_version(doc: &Value) -> u64 {
+    doc["schema_version"].as_u64().unwrap_or(0)
+}
+
+/// Brings a saved document up to `SCHEMA_VERSION` one step at a time. Documents written by a newer
+/// binary are refused with an `Unsupported` error so they never get overwritten with less data.
+pub fn migrate(mut doc: Value) -> Result<Value, std::io::Error> {
+    let version = get_version(&doc);
+    if version > SCHEMA_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "Save data is schema version {} but this build only understands up to {}",
+                version, SCHEMA_VERSION
+            ),
+        ));
+    }
+    for (from, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        println!("Migrating save data from version {} to {}", from, from + 1);
+        step(&mut doc);
+        doc["schema_version"] = json!(from + 1);
+    }
+    Ok(doc)
+}
+
+/// Migrates and deserializes a saved document.
+pub fn load(doc: Value) -> Result<ManagerData, std::io::Error> {
+    Ok(serde_json::from_value(migrate(doc)?)?)
+}
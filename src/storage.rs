@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::config::{Config, StorageKind};
+use crate::manager::{Assignment, Job, JobRun, ManagerData};
+use crate::outbox::OutboxEntry;
+use crate::schema;
+
+/// Where `ManagerData` lives between requests. `load` returns a `NotFound` error when nothing has
+/// been saved yet. The per-item methods get the whole `ManagerData` so backends that can only
+/// write everything at once (like the json file) can still implement them.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    fn load(&self) -> Result<ManagerData, std::io::Error>;
+    fn save(&self, data: &ManagerData) -> Result<(), std::io::Error>;
+    fn save_job(&self, data: &ManagerData, job: &Job) -> Result<(), std::io::Error>;
+    fn remove_job(&self, data: &ManagerData, name: &str) -> Result<(), std::io::Error>;
+    fn save_assignment(
+        &self,
+        data: &ManagerData,
+        assignment: &Assignment,
+    ) -> Result<(), std::io::Error>;
+    /// Also writes the assignment's outbox entries, since deleting it can queue taking it off the
+    /// calendar.
+    fn remove_assignment(
+        &self,
+        data: &ManagerData,
+        assignment: &Assignment,
+    ) -> Result<(), std::io::Error>;
+}
+
+/// The storage backend picked in the config, opened once per process. A backend that can't be
+/// opened keeps failing with the same error.
+pub fn get_storage() -> Result<&'static dyn Storage, std::io::Error> {
+    static STORAGE: OnceLock<Result<Box<dyn Storage>, String>> = OnceLock::new();
+    STORAGE
+        .get_or_init(|| match Config::get().storage {
+            StorageKind::Json => Ok(Box::new(JsonStorage::new(JsonStorage::get_save_file()))),
+            StorageKind::Sqlite => SqliteStorage::open(SqliteStorage::get_save_file())
+                .map(|storage| Box::new(storage) as Box<dyn Storage>)
+                .map_err(|e| format!("Could not open sqlite database: {}", e)),
+        })
+        .as_ref()
+        .map(|storage| storage.as_ref())
+        .map_err(|e| std::io::Error::other(e.clone()))
+}
+
+fn to_io_error(e: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+/// Keeps everything in one json document that is rewritten on every save. Writes go through a
+/// temp file and a rename so a crash can't leave a half written file behind, and older versions
+/// are kept in `backups/` to recover from if the live file is ever unreadable.
+#[derive(Debug)]
+pub struct JsonStorage {
+    save_file: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn get_save_file() -> PathBuf {
+        Config::get_data_dir().join("manager.json")
+    }
+    pub fn new(save_file: PathBuf) -> Self {
+        Self { save_file }
+    }
+
+    fn get_backup_dir(&self) -> PathBuf {
+        self.save_file.parent().unwrap().join("backups")
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Backups sorted newest first. The timestamp is part of the name so sorting by name works.
+    fn list_backups(&self) -> Vec<PathBuf> {
+        let mut backups: Vec<PathBuf> = match std::fs::read_dir(self.get_backup_dir()) {
+            Ok(dir) => dir
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("manager-") && name.ends_with(".json"))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        backups.sort();
+        backups.reverse();
+        backups
+    }
+
+    fn backup_time(path: &Path) -> u64 {
+        path.file_stem()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("manager-"))
+            .and_then(|time| time.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Copies the live file into `backups/` if the newest backup is old enough, then drops the
+    /// oldest backups past `backup_count`.
+    fn rotate_backups(&self) -> Result<(), std::io::Error> {
+        let config = Config::get();
+        if config.backup_count == 0 || !self.save_file.exists() {
+            return Ok(());
+        }
+        let now = Self::now();
+        let backups = self.list_backups();
+        if let Some(newest) = backups.first() {
+            if Self::backup_time(newest) + config.backup_interval > now {
+                return Ok(());
+            }
+        }
+        std::fs::create_dir_all(self.get_backup_dir())?;
+        let backup = self
+            .get_backup_dir()
+            .join(format!("manager-{:020}.json", now));
+        std::fs::copy(&self.save_file, backup)?;
+        for old in self.list_backups().iter().skip(config.backup_count) {
+            std::fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    /// Also says whether the file had to be migrated, so the caller can write the upgraded data
+    /// back. Migrations can hand out new ids, and those have to stick.
+    fn read(path: &Path) -> Result<(ManagerData, bool), std::io::Error> {
+        let file = std::fs::read_to_string(path)?;
+        let doc: serde_json::Value = serde_json::from_str(&file)?;
+        let migrated = schema::get_version(&doc) < schema::SCHEMA_VERSION;
+        Ok((schema::load(doc)?, migrated))
+    }
+
+    /// Loads the newest backup that still parses.
+    fn recover(&self) -> Option<ManagerData> {
+        for backup in self.list_backups() {
+            match Self::read(&backup) {
+                Ok((data, _)) => {
+                    println!("Recovered save data from {:?}", backup);
+                    return Some(data);
+                }
+                Err(e) => println!("Backup {:?} is unreadable: {}", backup, e),
+            }
+        }
+        None
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> Result<ManagerData, std::io::Error> {
+        let e = match Self::read(&self.save_file) {
+            Ok((data, migrated)) => {
+                if migrated {
+                    self.save(&data)?;
+                }
+                return Ok(data);
+            }
+            Err(e) => e,
+        };
+        if e.kind() == std::io::ErrorKind::Unsupported {
+            return Err(e);
+        }
+        if e.kind() != std::io::ErrorKind::NotFound {
+            println!("Save file {:?} is unreadable: {}", self.save_file, e);
+            // keep the broken file around instead of letting the next save overwrite it
+            let corrupt = self
+                .save_file
+                .with_extension(format!("json.corrupt-{}", Self::now()));
+            std::fs::rename(&self.save_file, &corrupt)?;
+            println!("Moved it to {:?}", corrupt);
+        }
+        match self.recover() {
+            Some(data) => {
+                self.save(&data)?;
+                Ok(data)
+            }
+            None => Err(e),
+        }
+    }
+
+    fn save(&self, data: &ManagerData) -> Result<(), std::io::Error> {
+        let json = serde_json::to_string(data)?;
+        let dir = self.save_file.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        if let Err(e) = self.rotate_backups() {
+            println!("Could not back up save file: {}", e);
+        }
+        let tmp = self.save_file.with_extension("json.tmp");
+        {
+            let mut file = std::fs::File::create(&tmp)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &self.save_file)?;
+        // make the rename itself durable
+        std::fs::File::open(dir)?.sync_all()
+    }
+
+    fn save_job(&self, data: &ManagerData, _job: &Job) -> Result<(), std::io::Error> {
+        self.save(data)
+    }
+    fn remove_job(&self, data: &ManagerData, _name: &str) -> Result<(), std::io::Error> {
+        self.save(data)
+    }
+    fn save_assignment(
+        &self,
+        data: &ManagerData,
+        _assignment: &Assignment,
+    ) -> Result<(), std::io::Error> {
+        self.save(data)
+    }
+    fn remove_assignment(
+        &self,
+        data: &ManagerData,
+        _assignment: &Assignment,
+    ) -> Result<(), std::io::Error> {
+        self.save(data)
+    }
+}
+
+fn upsert_job(conn: &Connection, job: &Job, data: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO jobs (name, data) VALUES (?1, ?2)
+         ON CONFLICT(name) DO UPDATE SET data = excluded.data",
+        params![job.name, data],
+    )
+}
+
+fn upsert_assignment(
+    conn: &Connection,
+    assignment: &Assignment,
+    data: &str,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO assignments (key, data) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET data = excluded.data",
+        params![assignment.key(), data],
+    )
+}
+
+fn upsert_run(conn: &Connection, run: &JobRun, data: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO runs (key, job_id, data) VALUES (?1, ?2, ?3)
+         ON CONFLICT(key) DO UPDATE SET job_id = excluded.job_id, data = excluded.data",
+        params![run.id, run.job_id, data],
+    )
+}
+
+fn upsert_entry(conn: &Connection, entry: &OutboxEntry, data: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO outbox (key, data) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET data = excluded.data",
+        params![entry.id, data],
+    )
+}
+
+/// Upserts the items whose row isn't what they serialize to and deletes the rows no item has, so
+/// a save only writes what changed.
+fn sync_rows<T: Serialize>(
+    conn: &Connection,
+    table: &str,
+    key_column: &str,
+    items: &[T],
+    key: impl Fn(&T) -> String,
+    upsert: impl Fn(&Connection, &T, &str) -> rusqlite::Result<usize>,
+) -> Result<(), std::io::Error> {
+    let mut stored: HashMap<String, String> = conn
+        .prepare(&format!("SELECT {}, data FROM {}", key_column, table))
+        .map_err(to_io_error)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(to_io_error)?
+        .collect::<Result<_, _>>()
+        .map_err(to_io_error)?;
+    for item in items {
+        let data = serde_json::to_string(item)?;
+        if stored.remove(&key(item)).as_ref() != Some(&data) {
+            upsert(conn, item, &data).map_err(to_io_error)?;
+        }
+    }
+    for key in stored.keys() {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE {} = ?1", table, key_column),
+            params![key],
+        )
+        .map_err(to_io_error)?;
+    }
+    Ok(())
+}
+
+/// Stores every job and assignment as its own row so single edits don't rewrite everything. Rows
+/// hold the serialized item, which keeps the table layout stable as the structs grow fields.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn get_save_file() -> PathBuf {
+        Config::get_data_dir().join("manager.db")
+    }
+
+    /// Opens (or creates) the database. A brand new database is seeded from `manager.json` when
+    /// one exists, so switching backends keeps the existing data.
+    pub fn open(path: PathBuf) -> Result<Self, std::io::Error> {
+        let is_new = !path.exists();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let conn = Connection::open(&path).map_err(to_io_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (name TEXT PRIMARY KEY, data TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS assignments (key TEXT PRIMARY KEY, data TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS runs (key TEXT PRIMARY KEY, job_id TEXT NOT NULL, data TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS outbox (key TEXT PRIMARY KEY, data TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+        )
+        .map_err(to_io_error)?;
+        let ret = Self {
+            conn: Mutex::new(conn),
+        };
+        if is_new {
+            if let Ok(data) = JsonStorage::new(JsonStorage::get_save_file()).load() {
+                println!("Importing manager.json into {:?}", path);
+                ret.save(&data)?;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> Result<ManagerData, std::io::Error> {
+        let conn = self.conn.lock().unwrap();
+        let saved: Option<String> = conn
+            .query_row("SELECT value FROM meta WHERE key = 'saved'", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(to_io_error)?;
+        if saved.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Database has not been saved to yet",
+            ));
+        }
+        let version: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io_error)?;
+        let version: u64 = version.and_then(|v| v.parse().ok()).unwrap_or(0);
+        let mut doc = serde_json::json!({
+            "jobs": [],
+            "assignments": [],
+            "runs": [],
+            "outbox": [],
+            "save_file": Self::get_save_file(),
+            "schema_version": version,
+        });
+        for table in ["jobs", "assignments", "runs", "outbox"] {
+            let mut stmt = conn
+                .prepare(&format!("SELECT data FROM {} ORDER BY rowid", table))
+                .map_err(to_io_error)?;
+            for row in stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(to_io_error)?
+            {
+                let item: serde_json::Value = serde_json::from_str(&row.map_err(to_io_error)?)?;
+                doc[table].as_array_mut().unwrap().push(item);
+            }
+        }
+        drop(conn);
+        let data = schema::load(doc)?;
+        if version < schema::SCHEMA_VERSION {
+            // rewrite every row so they all match the new version
+            self.save(&data)?;
+        }
+        Ok(data)
+    }
+
+    fn save(&self, data: &ManagerData) -> Result<(), std::io::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(to_io_error)?;
+        sync_rows(
+            &tx,
+            "jobs",
+            "name",
+            &data.jobs,
+            |job| job.name.clone(),
+            upsert_job,
+        )?;
+        sync_rows(
+            &tx,
+            "assignments",
+            "key",
+            &data.assignments,
+            Assignment::key,
+            upsert_assignment,
+        )?;
+        sync_rows(
+            &tx,
+            "runs",
+            "key",
+            &data.runs,
+            |run| run.id.clone(),
+            upsert_run,
+        )?;
+        sync_rows(
+            &tx,
+            "outbox",
+            "key",
+            &data.outbox,
+            |entry| entry.id.clone(),
+            upsert_entry,
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('saved', '1')",
+            [],
+        )
+        .map_err(to_io_error)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', ?1)",
+            params![schema::SCHEMA_VERSION.to_string()],
+        )
+        .map_err(to_io_error)?;
+        tx.commit().map_err(to_io_error)
+    }
+
+    fn save_job(&self, _data: &ManagerData, job: &Job) -> Result<(), std::io::Error> {
+        let conn = self.conn.lock().unwrap();
+        upsert_job(&conn, job, &serde_json::to_string(job)?).map_err(to_io_error)?;
+        Ok(())
+    }
+    fn remove_job(&self, _data: &ManagerData, name: &str) -> Result<(), std::io::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM runs WHERE job_id = (SELECT json_extract(data, '$.id') FROM jobs WHERE name = ?1)",
+            params![name],
+        )
+        .map_err(to_io_error)?;
+        conn.execute("DELETE FROM jobs WHERE name = ?1", params![name])
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+    fn save_assignment(
+        &self,
+        _data: &ManagerData,
+        assignment: &Assignment,
+    ) -> Result<(), std::io::Error> {
+        let conn = self.conn.lock().unwrap();
+        upsert_assignment(&conn, assignment, &serde_json::to_string(assignment)?)
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+    fn remove_assignment(
+        &self,
+        data: &ManagerData,
+        assignment: &Assignment,
+    ) -> Result<(), std::io::Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(to_io_error)?;
+        tx.execute(
+            "DELETE FROM assignments WHERE key = ?1",
+            params![assignment.key()],
+        )
+        .map_err(to_io_error)?;
+        tx.execute(
+            "DELETE FROM outbox WHERE json_extract(data, '$.assignment_id') = ?1",
+            params![assignment.id],
+        )
+        .map_err(to_io_error)?;
+        for entry in data
+            .outbox
+            .iter()
+            .filter(|entry| entry.assignment_id == assignment.id)
+        {
+            upsert_entry(&tx, entry, &serde_json::to_string(entry)?).map_err(to_io_error)?;
+        }
+        tx.commit().map_err(to_io_error)
+    }
+}
@@ -11,3 +11,9 @@ setTimeout(() => {
     el.textContent = new Date(parseInt(el.textContent) * 1000).toLocaleString();
   });
 });
+setTimeout(() => {
+  document.querySelectorAll(".date").forEach((el) => {
+    if (!el.textContent.match(/^[0-9]+$/)) return;
+    el.textContent = new Date(parseInt(el.textContent) * 1000).toLocaleDateString();
+  });
+});